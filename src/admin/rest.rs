@@ -0,0 +1,280 @@
+use actix_web::{web, HttpResponse, Responder, Result};
+use chrono::Utc;
+use serde::Deserialize;
+
+use crate::mailersend::{
+    buffer::Buffer,
+    dead_letter::DeadLetterStore,
+    delivery_log::DeliveryLog,
+    inbox::{InboxStatus, WebhookInbox},
+};
+
+#[derive(Deserialize, Debug)]
+pub struct Pagination {
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+pub async fn list_dead_letters(
+    dead_letters: web::Data<DeadLetterStore>,
+    pagination: web::Query<Pagination>,
+) -> Result<impl Responder> {
+    let limit = pagination.limit.unwrap_or(100);
+    let offset = pagination.offset.unwrap_or(0);
+    match dead_letters.list(limit, offset) {
+        Ok(dead_letters) => Ok(HttpResponse::Ok().json(dead_letters)),
+        Err(e) => {
+            log::error!("Failed to list dead letters: {}", e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+pub async fn get_dead_letter(
+    dead_letters: web::Data<DeadLetterStore>,
+    id: web::Path<i64>,
+) -> Result<impl Responder> {
+    match dead_letters.get(*id) {
+        Ok(Some(dead_letter)) => Ok(HttpResponse::Ok().json(dead_letter)),
+        Ok(None) => Ok(HttpResponse::NotFound().finish()),
+        Err(e) => {
+            log::error!("Failed to read dead letter {}: {}", id, e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+pub async fn delete_dead_letter(
+    dead_letters: web::Data<DeadLetterStore>,
+    id: web::Path<i64>,
+) -> Result<impl Responder> {
+    match dead_letters.delete(*id) {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().finish()),
+        Err(e) => {
+            log::error!("Failed to delete dead letter {}: {}", id, e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+pub async fn replay_dead_letter(
+    dead_letters: web::Data<DeadLetterStore>,
+    email_buffer: web::Data<Buffer>,
+    id: web::Path<i64>,
+) -> Result<impl Responder> {
+    let dead_letter = match dead_letters.get(*id) {
+        Ok(Some(dead_letter)) => dead_letter,
+        Ok(None) => return Ok(HttpResponse::NotFound().finish()),
+        Err(e) => {
+            log::error!("Failed to read dead letter {}: {}", id, e);
+            return Ok(HttpResponse::InternalServerError().finish());
+        }
+    };
+    if let Err(e) = email_buffer.push_all(vec![dead_letter.email]).await {
+        log::error!("Failed to queue dead letter {}: {}", id, e);
+        return Ok(HttpResponse::InternalServerError().finish());
+    }
+    if let Err(e) = dead_letters.delete(*id) {
+        log::error!("Failed to delete replayed dead letter {}: {}", id, e);
+    }
+    log::info!("Replayed dead letter {}", id);
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebhookFilter {
+    status: Option<InboxStatus>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+pub async fn list_webhooks(
+    webhook_inbox: web::Data<WebhookInbox>,
+    filter: web::Query<WebhookFilter>,
+) -> Result<impl Responder> {
+    let limit = filter.limit.unwrap_or(100);
+    let offset = filter.offset.unwrap_or(0);
+    match webhook_inbox.list(filter.status, limit, offset) {
+        Ok(events) => Ok(HttpResponse::Ok().json(events)),
+        Err(e) => {
+            log::error!("Failed to list webhooks: {}", e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+pub async fn get_webhook(
+    webhook_inbox: web::Data<WebhookInbox>,
+    id: web::Path<i64>,
+) -> Result<impl Responder> {
+    match webhook_inbox.get(*id) {
+        Ok(Some(event)) => Ok(HttpResponse::Ok().json(event)),
+        Ok(None) => Ok(HttpResponse::NotFound().finish()),
+        Err(e) => {
+            log::error!("Failed to read webhook {}: {}", id, e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+pub async fn retry_webhook(
+    webhook_inbox: web::Data<WebhookInbox>,
+    id: web::Path<i64>,
+) -> Result<impl Responder> {
+    match webhook_inbox.requeue(*id, Utc::now()) {
+        Ok(true) => {
+            log::info!("Requeued webhook {}", id);
+            Ok(HttpResponse::Accepted().finish())
+        }
+        Ok(false) => Ok(HttpResponse::NotFound().finish()),
+        Err(e) => {
+            log::error!("Failed to requeue webhook {}: {}", id, e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+pub async fn campaign_events(
+    delivery_log: web::Data<DeliveryLog>,
+    campaign_uuid: web::Path<String>,
+    pagination: web::Query<Pagination>,
+) -> Result<impl Responder> {
+    let limit = pagination.limit.unwrap_or(100);
+    let offset = pagination.offset.unwrap_or(0);
+    match delivery_log.campaign_timeline(&campaign_uuid, limit, offset) {
+        Ok(events) => Ok(HttpResponse::Ok().json(events)),
+        Err(e) => {
+            log::error!("Failed to read events of campaign {}: {}", campaign_uuid, e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+pub async fn recipient_events(
+    delivery_log: web::Data<DeliveryLog>,
+    recipient: web::Path<String>,
+    pagination: web::Query<Pagination>,
+) -> Result<impl Responder> {
+    let limit = pagination.limit.unwrap_or(100);
+    let offset = pagination.offset.unwrap_or(0);
+    match delivery_log.recipient_timeline(&recipient, limit, offset) {
+        Ok(events) => Ok(HttpResponse::Ok().json(events)),
+        Err(e) => {
+            log::error!("Failed to read events of recipient {}: {}", recipient, e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mailersend::api::Email;
+    use crate::storage::Database;
+    use actix_web::{http::StatusCode, test::TestRequest};
+
+    fn email() -> Email {
+        Email::for_test("to@email.com")
+    }
+
+    #[actix_rt::test]
+    async fn test_replay_dead_letter() {
+        let database = Database::open_in_memory().unwrap();
+        let dead_letters = web::Data::new(DeadLetterStore::new(database.clone()).unwrap());
+        let email_buffer = web::Data::new(Buffer::new(database).unwrap());
+        dead_letters.add_all(vec![email()], 422, "{}").unwrap();
+        let id = dead_letters.list(10, 0).unwrap()[0].id;
+
+        let request = TestRequest::default().to_http_request();
+        let response = replay_dead_letter(
+            dead_letters.clone(),
+            email_buffer.clone(),
+            web::Path::from(id),
+        )
+        .await
+        .unwrap()
+        .respond_to(&request);
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(dead_letters.get(id).unwrap().is_none());
+        let queued = email_buffer.pop_all().await.unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].email.to, email().to);
+
+        let response = replay_dead_letter(dead_letters, email_buffer, web::Path::from(id))
+            .await
+            .unwrap()
+            .respond_to(&request);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn test_list_and_retry_failed_webhooks() {
+        let webhook_inbox =
+            web::Data::new(WebhookInbox::new(Database::open_in_memory().unwrap()).unwrap());
+        let payload = serde_json::from_str(include_str!("../../test/req_bounce.json")).unwrap();
+        let failed = webhook_inbox.push(&payload, Utc::now()).unwrap();
+        webhook_inbox.push(&payload, Utc::now()).unwrap();
+        webhook_inbox.claim_due(Utc::now()).unwrap();
+        webhook_inbox.fail(failed, "Invalid recipient").unwrap();
+
+        let request = TestRequest::default().to_http_request();
+        let filter = web::Query::<WebhookFilter>::from_query("status=failed").unwrap();
+        let response = list_webhooks(webhook_inbox.clone(), filter)
+            .await
+            .unwrap()
+            .respond_to(&request);
+        assert_eq!(response.status(), StatusCode::OK);
+        let Ok(body) = actix_web::body::to_bytes(response.into_body()).await else {
+            panic!("Failed to read response body");
+        };
+        let events: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(events.as_array().unwrap().len(), 1);
+        assert_eq!(events[0]["id"], failed);
+        assert_eq!(events[0]["status"], "failed");
+        assert_eq!(events[0]["payload"]["type"], "activity.soft_bounced");
+
+        let response = retry_webhook(webhook_inbox.clone(), web::Path::from(failed))
+            .await
+            .unwrap()
+            .respond_to(&request);
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let response = retry_webhook(webhook_inbox, web::Path::from(failed))
+            .await
+            .unwrap()
+            .respond_to(&request);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn test_recipient_events() {
+        let delivery_log =
+            web::Data::new(DeliveryLog::new(Database::open_in_memory().unwrap()).unwrap());
+        delivery_log
+            .add_sends(
+                &[email()],
+                crate::mailersend::delivery_log::SEND_ACCEPTED,
+                serde_json::json!({"bulk_email_id": "bulk-1"}),
+            )
+            .unwrap();
+
+        let request = TestRequest::default().to_http_request();
+        let pagination = web::Query::<Pagination>::from_query("").unwrap();
+        let response = recipient_events(
+            delivery_log,
+            web::Path::from("TO@email.com".to_string()),
+            pagination,
+        )
+        .await
+        .unwrap()
+        .respond_to(&request);
+        assert_eq!(response.status(), StatusCode::OK);
+        let Ok(body) = actix_web::body::to_bytes(response.into_body()).await else {
+            panic!("Failed to read response body");
+        };
+        let events: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(events[0]["event"], "send.accepted");
+        assert_eq!(events[0]["recipient"], "to@email.com");
+        assert_eq!(events[0]["details"]["bulk_email_id"], "bulk-1");
+    }
+}
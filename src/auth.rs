@@ -0,0 +1,160 @@
+use actix_web::{
+    body::EitherBody,
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{HeaderMap, AUTHORIZATION, WWW_AUTHENTICATE},
+    web, Error, HttpResponse,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::future::{ready, LocalBoxFuture};
+use subtle::ConstantTimeEq;
+
+const REALM: &str = "Basic realm=\"listmonk-mailersend\"";
+
+/// Credentials accepted on the messenger and admin endpoints.
+#[derive(Clone)]
+pub struct Authenticator {
+    basic: Option<String>,
+    bearer: Option<String>,
+}
+
+impl Authenticator {
+    pub fn new(username: Option<String>, password: Option<String>, token: Option<String>) -> Self {
+        Authenticator {
+            basic: username.map(|x| format!("{}:{}", x, password.unwrap_or_default())),
+            bearer: token,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.basic.is_some() || self.bearer.is_some()
+    }
+
+    /// Checks the `Authorization` header against the configured basic
+    /// credentials or bearer token. Every request is accepted when neither
+    /// is configured.
+    pub fn is_authorized(&self, headers: &HeaderMap) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        let authorization = match headers.get(AUTHORIZATION).and_then(|x| x.to_str().ok()) {
+            None => return false,
+            Some(authorization) => authorization.trim(),
+        };
+        let (scheme, value) = match authorization.split_once(' ') {
+            None => return false,
+            Some((scheme, value)) => (scheme, value.trim()),
+        };
+        if scheme.eq_ignore_ascii_case("basic") {
+            let credentials = match STANDARD.decode(value) {
+                Err(_) => return false,
+                Ok(credentials) => credentials,
+            };
+            return matches(self.basic.as_deref(), &credentials);
+        }
+        if scheme.eq_ignore_ascii_case("bearer") {
+            return matches(self.bearer.as_deref(), value.as_bytes());
+        }
+        false
+    }
+}
+
+fn matches(expected: Option<&str>, actual: &[u8]) -> bool {
+    expected.is_some_and(|x| x.as_bytes().ct_eq(actual).into())
+}
+
+/// Middleware rejecting requests not authorized by the `Authenticator` in
+/// the application data, for use with `wrap_fn`.
+pub fn authenticate<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    let authorized = req
+        .app_data::<web::Data<Authenticator>>()
+        .is_some_and(|x| x.is_authorized(req.headers()));
+    if !authorized {
+        log::warn!("Rejecting unauthorized request to {}", req.path());
+        let response = HttpResponse::Unauthorized()
+            .insert_header((WWW_AUTHENTICATE, REALM))
+            .finish();
+        return Box::pin(ready(Ok(req.into_response(response).map_into_right_body())));
+    }
+    let response = srv.call(req);
+    Box::pin(async move { Ok(response.await?.map_into_left_body()) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test::TestRequest, App};
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(
+            Some("listmonk".to_string()),
+            Some("secret".to_string()),
+            Some("token".to_string()),
+        )
+    }
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, authorization.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_basic_credentials() {
+        let authenticator = authenticator();
+        let valid = format!("Basic {}", STANDARD.encode("listmonk:secret"));
+        let invalid = format!("Basic {}", STANDARD.encode("listmonk:wrong"));
+        assert!(authenticator.is_authorized(&headers(&valid)));
+        assert!(!authenticator.is_authorized(&headers(&invalid)));
+        assert!(!authenticator.is_authorized(&headers("Basic %%%")));
+        assert!(!authenticator.is_authorized(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let authenticator = authenticator();
+        assert!(authenticator.is_authorized(&headers("Bearer token")));
+        assert!(!authenticator.is_authorized(&headers("Bearer other")));
+        let basic_only = Authenticator::new(Some("listmonk".to_string()), None, None);
+        assert!(!basic_only.is_authorized(&headers("Bearer token")));
+    }
+
+    #[test]
+    fn test_disabled_authenticator_accepts_everything() {
+        let authenticator = Authenticator::new(None, None, None);
+        assert!(!authenticator.is_enabled());
+        assert!(authenticator.is_authorized(&HeaderMap::new()));
+    }
+
+    #[actix_rt::test]
+    async fn test_authenticate_middleware() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(authenticator()))
+                .service(
+                    web::resource("/api/messenger")
+                        .wrap_fn(authenticate)
+                        .route(web::post().to(HttpResponse::Ok)),
+                ),
+        )
+        .await;
+        let request = TestRequest::post().uri("/api/messenger").to_request();
+        let response = actix_web::test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key(WWW_AUTHENTICATE));
+
+        let request = TestRequest::post()
+            .uri("/api/messenger")
+            .insert_header((AUTHORIZATION, "Bearer token"))
+            .to_request();
+        let response = actix_web::test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
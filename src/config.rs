@@ -1,6 +1,22 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use log;
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum UnsubscribeAction {
+    /// Unsubscribe from the lists of the campaign the email was sent for
+    Unsubscribe,
+    /// Blocklist the subscriber
+    Blocklist,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum UnknownCampaignAction {
+    /// Unsubscribe from all the lists of the subscriber
+    UnsubscribeAll,
+    /// Blocklist the subscriber
+    Blocklist,
+}
+
 #[derive(Parser, Debug, Clone)]
 pub struct Configuration {
     #[arg(long, short = 'H', env, default_value_t = String::from("127.0.0.1"), help="Service bind IP address")]
@@ -18,6 +34,23 @@ pub struct Configuration {
     #[arg(long, short = 'c', env, help = "Outgoing cron schedule", default_value_t = String::from("0 */1 * * * * *"))]
     pub outgoing_cron: String,
 
+    #[arg(long, env, help = "MailSender bulk email status polling cron schedule", default_value_t = String::from("30 */1 * * * * *"))]
+    pub bulk_status_cron: String,
+
+    #[arg(long, env, help = "MailerSend webhook inbox processing cron schedule", default_value_t = String::from("*/10 * * * * * *"))]
+    pub webhook_inbox_cron: String,
+
+    #[arg(long, env, help = "Delivery event log pruning cron schedule", default_value_t = String::from("0 0 3 * * * *"))]
+    pub delivery_log_prune_cron: String,
+
+    #[arg(
+        long,
+        env,
+        help = "Days for which send attempts and MailerSend webhook events are kept in the delivery log",
+        default_value_t = 90
+    )]
+    pub delivery_log_retention_days: i64,
+
     #[arg(long, short = 'l', env, default_value_t = log::Level::Info, help="Log level")]
     pub log_level: log::Level,
 
@@ -30,6 +63,13 @@ pub struct Configuration {
     #[arg(long, short = 'm', env, help = "Listmonk API endpoint", default_value_t = String::from("http://localhost:9001"))]
     pub listmonk_api_endpoint: String,
 
+    #[arg(
+        long,
+        env,
+        help = "Listmonk public URL used in unsubscribe links, defaults to the API endpoint"
+    )]
+    pub listmonk_public_url: Option<String>,
+
     #[arg(long, short = 'u', env, help = "Listmonk API username")]
     pub listmonk_api_username: String,
 
@@ -54,6 +94,182 @@ pub struct Configuration {
     )]
     pub api_bulk_req_per_min: u32,
 
+    #[arg(
+        long,
+        env,
+        help = "MailSender API requests allowed in a burst",
+        default_value_t = 1
+    )]
+    pub api_bulk_burst: u32,
+
+    #[arg(
+        long,
+        env,
+        help = "MailSender API attempts per bulk request",
+        default_value_t = 5
+    )]
+    pub api_retry_attempts: u32,
+
+    #[arg(
+        long,
+        env,
+        help = "MailSender API initial retry delay in milliseconds",
+        default_value_t = 1000
+    )]
+    pub api_retry_base_delay_ms: u64,
+
+    #[arg(
+        long,
+        env,
+        help = "MailSender API maximum retry delay in milliseconds",
+        default_value_t = 60000
+    )]
+    pub api_retry_max_delay_ms: u64,
+
+    #[arg(
+        long,
+        env,
+        value_delimiter = ',',
+        help = "Campaign headers forwarded to MailSender, all non-reserved headers when empty"
+    )]
+    pub allowed_headers: Vec<String>,
+
+    #[arg(
+        long,
+        env,
+        value_delimiter = ',',
+        help = "Campaign headers never forwarded to MailSender"
+    )]
+    pub denied_headers: Vec<String>,
+
+    #[arg(
+        long,
+        env,
+        value_delimiter = ',',
+        help = "Sender domains or addresses allowed as campaign From, all senders when empty"
+    )]
+    pub allowed_senders: Vec<String>,
+
+    #[arg(
+        long,
+        env,
+        help = "From address replacing disallowed campaign senders, which become the Reply-To"
+    )]
+    pub default_from: Option<String>,
+
+    #[arg(
+        long,
+        env,
+        help = "Soft bounces of an address within the window at which one hard bounce is recorded, 0 forwards every soft bounce",
+        default_value_t = 3
+    )]
+    pub soft_bounce_threshold: u32,
+
+    #[arg(
+        long,
+        env,
+        help = "Soft bounces of an address within the window after which it is blocklisted"
+    )]
+    pub soft_bounce_blocklist_threshold: Option<u32>,
+
+    #[arg(
+        long,
+        env,
+        help = "Sliding window in hours over which soft bounces are counted",
+        default_value_t = 168
+    )]
+    pub soft_bounce_window_hours: i64,
+
+    #[arg(
+        long,
+        env,
+        help = "Hours for which processed MailerSend webhook event ids are remembered to ignore redeliveries",
+        default_value_t = 72
+    )]
+    pub webhook_dedupe_ttl_hours: i64,
+
+    #[arg(
+        long,
+        env,
+        help = "Maximum attempts at applying a MailerSend webhook to listmonk",
+        default_value_t = 10
+    )]
+    pub webhook_retry_attempts: u32,
+
+    #[arg(
+        long,
+        env,
+        help = "Base delay in seconds between attempts at applying a MailerSend webhook",
+        default_value_t = 30
+    )]
+    pub webhook_retry_base_delay_secs: u64,
+
+    #[arg(
+        long,
+        env,
+        help = "Maximum delay in seconds between attempts at applying a MailerSend webhook",
+        default_value_t = 3600
+    )]
+    pub webhook_retry_max_delay_secs: u64,
+
+    #[arg(
+        long,
+        env,
+        value_enum,
+        default_value_t = UnsubscribeAction::Unsubscribe,
+        help = "What to do in listmonk when a recipient unsubscribes through MailSender"
+    )]
+    pub unsubscribe_action: UnsubscribeAction,
+
+    #[arg(
+        long,
+        env,
+        value_enum,
+        default_value_t = UnknownCampaignAction::UnsubscribeAll,
+        help = "What to do in listmonk when the campaign of an unsubscribed recipient cannot be found"
+    )]
+    pub unknown_campaign_action: UnknownCampaignAction,
+
+    #[arg(
+        long,
+        env,
+        default_value_t = false,
+        help = "Forward opens tracked by MailSender to listmonk instead of the listmonk pixel"
+    )]
+    pub forward_tracking: bool,
+
     #[arg(long, short = 's', env, help = "MailSender Webhooks signing secret")]
     pub signing_secret: Option<String>,
+
+    #[arg(
+        long,
+        env,
+        default_value_t = false,
+        help = "Refuse to start without a MailSender Webhooks signing secret"
+    )]
+    pub require_signing_secret: bool,
+
+    #[arg(
+        long,
+        env,
+        help = "Username required on the messenger and admin endpoints, as configured in listmonk"
+    )]
+    pub auth_username: Option<String>,
+
+    #[arg(
+        long,
+        env,
+        help = "Password required on the messenger and admin endpoints, as configured in listmonk"
+    )]
+    pub auth_password: Option<String>,
+
+    #[arg(
+        long,
+        env,
+        help = "Bearer token accepted on the messenger and admin endpoints"
+    )]
+    pub auth_token: Option<String>,
+
+    #[arg(long, short = 'd', env, help = "SQLite database path", default_value_t = String::from("listmonk-mailersend.db"))]
+    pub database_path: String,
 }
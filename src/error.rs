@@ -0,0 +1,108 @@
+use actix_web::{error::JsonPayloadError, http::StatusCode, HttpResponse, ResponseError};
+use thiserror::Error;
+
+use crate::listmonk::{attachments::AttachmentError, rest::MessengerResponse};
+
+/// Errors returned by the listmonk messenger and MailerSend webhook endpoints.
+#[derive(Error, Debug)]
+pub enum RestError {
+    #[error("Invalid sender address {0}")]
+    InvalidSender(String),
+
+    #[error("Sender {0} is not allowed")]
+    SenderNotAllowed(String),
+
+    #[error("Invalid recipient address {0}")]
+    InvalidRecipient(String),
+
+    #[error("No valid recipients")]
+    NoValidRecipients,
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Request too large: {0}")]
+    RequestTooLarge(String),
+
+    #[error(transparent)]
+    Attachment(#[from] AttachmentError),
+
+    #[error("Invalid webhook signature")]
+    InvalidSignature,
+
+    #[error("Invalid webhook request: {0}")]
+    InvalidWebhook(String),
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] rusqlite::Error),
+
+    #[error("Listmonk API error: {0}")]
+    Listmonk(String),
+}
+
+impl ResponseError for RestError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            RestError::InvalidSender(_)
+            | RestError::SenderNotAllowed(_)
+            | RestError::InvalidRecipient(_)
+            | RestError::NoValidRecipients
+            | RestError::InvalidRequest(_)
+            | RestError::InvalidWebhook(_) => StatusCode::BAD_REQUEST,
+            RestError::RequestTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            RestError::Attachment(AttachmentError::TooLarge { .. }) => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            RestError::Attachment(AttachmentError::InvalidContent(_)) => StatusCode::BAD_REQUEST,
+            RestError::InvalidSignature => StatusCode::UNAUTHORIZED,
+            RestError::Storage(_) | RestError::Listmonk(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(MessengerResponse::error(&self.to_string()))
+    }
+}
+
+impl From<JsonPayloadError> for RestError {
+    fn from(err: JsonPayloadError) -> Self {
+        match err {
+            JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+                RestError::RequestTooLarge(err.to_string())
+            }
+            _ => RestError::InvalidRequest(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::MessageBody;
+
+    #[test]
+    fn test_error_response() {
+        let response = RestError::SenderNotAllowed("john@gmail.com".to_string()).error_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = response.into_body().try_into_bytes().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "error");
+        assert_eq!(body["message"], "Sender john@gmail.com is not allowed");
+    }
+
+    #[test]
+    fn test_error_status_codes() {
+        assert_eq!(
+            RestError::Attachment(AttachmentError::TooLarge { size: 2, max: 1 }).status_code(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            RestError::InvalidSignature.status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            RestError::Listmonk("timeout".to_string()).status_code(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
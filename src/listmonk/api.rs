@@ -1,4 +1,8 @@
-use reqwest::Client;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use reqwest::{Client, RequestBuilder, Response};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -6,6 +10,11 @@ use crate::mailersend::api::EmailAddress;
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+/// Campaigns are looked up a page at a time, newest first.
+const CAMPAIGNS_PAGE_SIZE: usize = 50;
+/// How long a campaign UUID not found in listmonk is not looked up again.
+const MISSING_CAMPAIGN_TTL: Duration = Duration::from_secs(15 * 60);
+
 #[derive(Error, Debug)]
 pub enum ListmonkApiError {
     #[error("Listmonk webhook failed: {0}")]
@@ -30,12 +39,73 @@ pub struct ListmonkBounce {
     source: String,
     #[serde(rename = "type")]
     bounce_type: BounceType,
-    meta: Option<String>,
+    meta: Option<BounceMeta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscriber_uuid: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct QueryBlocklistRequest {
-    query: String,
+/// Details of a MailerSend bounce, stored by listmonk along with the bounce.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct BounceMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiResponse<T> {
+    data: T,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResultsPage<T> {
+    results: Vec<T>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ListReference {
+    pub id: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Subscriber {
+    pub id: u64,
+    pub email: String,
+    #[serde(default)]
+    pub lists: Vec<ListReference>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Campaign {
+    pub uuid: String,
+    #[serde(default)]
+    pub lists: Vec<ListReference>,
+}
+
+#[derive(Serialize, Debug)]
+struct SubscriberListsRequest<'a> {
+    ids: &'a [u64],
+    action: &'a str,
+    target_list_ids: &'a [u64],
+}
+
+#[derive(Serialize, Debug)]
+struct SubscriberBlocklistRequest<'a> {
+    ids: &'a [u64],
+}
+
+/// Quotes a value for use in a listmonk subscriber SQL query.
+fn quote_sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
 }
 
 impl ListmonkBounce {
@@ -46,6 +116,7 @@ impl ListmonkBounce {
             source: "mailersend".to_string(),
             bounce_type,
             meta: None,
+            subscriber_uuid: None,
         };
     }
 
@@ -54,9 +125,14 @@ impl ListmonkBounce {
         return self;
     }
 
-    pub fn with_meta(mut self, meta: &str) -> Self {
-        self.meta = Some(meta.to_string());
-        return self;
+    pub fn with_subscriber_uuid(mut self, subscriber_uuid: &str) -> Self {
+        self.subscriber_uuid = Some(subscriber_uuid.to_string());
+        self
+    }
+
+    pub fn with_meta(mut self, meta: BounceMeta) -> Self {
+        self.meta = Some(meta);
+        self
     }
 }
 
@@ -66,6 +142,11 @@ pub struct ListmonkAPI {
     api_endpoint: String,
     api_username: String,
     api_password: String,
+    /// Campaigns by UUID. listmonk cannot filter campaigns by UUID, and the
+    /// lists of a sent campaign do not change.
+    campaigns: Arc<Mutex<HashMap<String, Campaign>>>,
+    /// Campaign UUIDs not found in listmonk, with when they were looked up.
+    missing_campaigns: Arc<Mutex<HashMap<String, Instant>>>,
 }
 
 impl ListmonkAPI {
@@ -76,6 +157,8 @@ impl ListmonkAPI {
             api_endpoint: api_endpoint.to_string(),
             api_username: api_username.to_string(),
             api_password: api_password.to_string(),
+            campaigns: Arc::new(Mutex::new(HashMap::new())),
+            missing_campaigns: Arc::new(Mutex::new(HashMap::new())),
         };
     }
 
@@ -101,17 +184,143 @@ impl ListmonkAPI {
         Ok(())
     }
 
+    /// Blocklists the subscriber with exactly the given email, if any.
     pub async fn blocklist_by_email(&self, email: EmailAddress) -> Result<()> {
+        match self.get_subscriber_by_email(&email).await? {
+            Some(subscriber) => self.blocklist_subscribers(&[subscriber.id]).await,
+            None => {
+                log::warn!("No listmonk subscriber with email {}", email.email());
+                Ok(())
+            }
+        }
+    }
+
+    /// Counts a campaign view as if the subscriber loaded the listmonk pixel.
+    pub async fn record_view(&self, campaign_uuid: &str, subscriber_uuid: &str) -> Result<()> {
+        let request = self.http_client.get(format!(
+            "{}/campaign/{}/{}/px.png",
+            self.api_endpoint, campaign_uuid, subscriber_uuid
+        ));
+        self.send(request).await?;
+        Ok(())
+    }
+
+    pub async fn get_subscriber_by_email(
+        &self,
+        email: &EmailAddress,
+    ) -> Result<Option<Subscriber>> {
+        let subscriber = self
+            .find_subscriber(&format!(
+                "LOWER(subscribers.email) = {}",
+                quote_sql_string(&email.email().to_lowercase())
+            ))
+            .await?;
+        Ok(subscriber.filter(|x| x.email.eq_ignore_ascii_case(email.email())))
+    }
+
+    pub async fn get_subscriber_by_uuid(
+        &self,
+        subscriber_uuid: &str,
+    ) -> Result<Option<Subscriber>> {
+        self.find_subscriber(&format!(
+            "subscribers.uuid = {}",
+            quote_sql_string(subscriber_uuid)
+        ))
+        .await
+    }
+
+    async fn find_subscriber(&self, query: &str) -> Result<Option<Subscriber>> {
         let request = self
             .http_client
-            .put(&format!(
-                "{}/api/subscribers/query/blocklist",
-                self.api_endpoint
-            ))
+            .get(format!("{}/api/subscribers", self.api_endpoint))
+            .basic_auth(&self.api_username, Some(&self.api_password))
+            .query(&[("query", query), ("per_page", "1")]);
+        let response = self.send(request).await?;
+        let page: ApiResponse<ResultsPage<Subscriber>> = response.json().await?;
+        Ok(page.data.results.into_iter().next())
+    }
+
+    pub async fn get_campaign_by_uuid(&self, campaign_uuid: &str) -> Result<Option<Campaign>> {
+        if let Some(campaign) = self.campaigns.lock().unwrap().get(campaign_uuid) {
+            return Ok(Some(campaign.clone()));
+        }
+        {
+            let mut missing_campaigns = self.missing_campaigns.lock().unwrap();
+            missing_campaigns
+                .retain(|_, looked_up_at| looked_up_at.elapsed() < MISSING_CAMPAIGN_TTL);
+            if missing_campaigns.contains_key(campaign_uuid) {
+                return Ok(None);
+            }
+        }
+        let per_page = CAMPAIGNS_PAGE_SIZE.to_string();
+        for page in 1.. {
+            let request = self
+                .http_client
+                .get(format!("{}/api/campaigns", self.api_endpoint))
+                .basic_auth(&self.api_username, Some(&self.api_password))
+                .query(&[
+                    ("order_by", "created_at"),
+                    ("order", "desc"),
+                    ("page", &page.to_string()),
+                    ("per_page", &per_page),
+                    ("no_body", "true"),
+                ]);
+            let response = self.send(request).await?;
+            let results = response
+                .json::<ApiResponse<ResultsPage<Campaign>>>()
+                .await?
+                .data
+                .results;
+            let last_page = results.len() < CAMPAIGNS_PAGE_SIZE;
+            let mut campaigns = self.campaigns.lock().unwrap();
+            for campaign in results {
+                campaigns.insert(campaign.uuid.clone(), campaign);
+            }
+            if let Some(campaign) = campaigns.get(campaign_uuid) {
+                return Ok(Some(campaign.clone()));
+            }
+            if last_page {
+                break;
+            }
+        }
+        self.missing_campaigns
+            .lock()
+            .unwrap()
+            .insert(campaign_uuid.to_string(), Instant::now());
+        Ok(None)
+    }
+
+    pub async fn unsubscribe_from_lists(
+        &self,
+        subscriber_ids: &[u64],
+        list_ids: &[u64],
+    ) -> Result<()> {
+        let request = self
+            .http_client
+            .put(format!("{}/api/subscribers/lists", self.api_endpoint))
             .basic_auth(&self.api_username, Some(&self.api_password))
-            .json(&QueryBlocklistRequest {
-                query: format!("subscribers.email LIKE '{}'", email.email()),
+            .json(&SubscriberListsRequest {
+                ids: subscriber_ids,
+                action: "unsubscribe",
+                target_list_ids: list_ids,
             });
+        self.send(request).await?;
+        Ok(())
+    }
+
+    pub async fn blocklist_subscribers(&self, subscriber_ids: &[u64]) -> Result<()> {
+        let request = self
+            .http_client
+            .put(format!("{}/api/subscribers/blocklist", self.api_endpoint))
+            .basic_auth(&self.api_username, Some(&self.api_password))
+            .json(&SubscriberBlocklistRequest {
+                ids: subscriber_ids,
+            });
+        self.send(request).await?;
+        Ok(())
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response> {
         log::info!("Sending request: {:?}", request);
         let response = request.send().await?;
         let response_status = response.status();
@@ -125,6 +334,156 @@ impl ListmonkAPI {
             .into());
         }
         log::info!("Listmonk API request successful");
-        Ok(())
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::listmonk::mock;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_quote_sql_string() {
+        assert_eq!(quote_sql_string("a@b.com"), "'a@b.com'");
+        assert_eq!(
+            quote_sql_string("o'brien@b.com' OR 1=1 --"),
+            "'o''brien@b.com'' OR 1=1 --'"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_get_subscriber_by_email() {
+        let (api, requests) = mock::listmonk(&[(
+            "GET /api/subscribers",
+            serde_json::json!({
+                "data": {
+                    "results": [{
+                        "id": 3,
+                        "uuid": "6a9d1d7e",
+                        "email": "anon@example.com",
+                        "name": "Anon",
+                        "lists": [{"id": 1, "name": "Default list"}]
+                    }],
+                    "total": 1
+                }
+            }),
+        )]);
+        let email = EmailAddress::from_string("Anon@Example.com").unwrap();
+        let subscriber = api.get_subscriber_by_email(&email).await.unwrap().unwrap();
+        assert_eq!(subscriber.id, 3);
+        assert_eq!(subscriber.lists, vec![ListReference { id: 1 }]);
+        let request = &requests.find("GET /api/subscribers")[0];
+        assert!(request.query.contains("per_page=1"));
+        assert!(request
+            .query
+            .contains("LOWER%28subscribers.email%29+%3D+%27anon%40example.com%27"));
+    }
+
+    #[actix_rt::test]
+    async fn test_get_campaign_by_uuid() {
+        let (api, requests) = mock::listmonk(&[(
+            "GET /api/campaigns",
+            serde_json::json!({
+                "data": {
+                    "results": [
+                        {"id": 1, "uuid": "other", "lists": []},
+                        {"id": 2, "uuid": "a4d516f2", "lists": [{"id": 4, "name": "News"}]}
+                    ]
+                }
+            }),
+        )]);
+        let campaign = api.get_campaign_by_uuid("a4d516f2").await.unwrap().unwrap();
+        assert_eq!(campaign.uuid, "a4d516f2");
+        assert_eq!(campaign.lists, vec![ListReference { id: 4 }]);
+        let lookup = &requests.find("GET /api/campaigns")[0];
+        assert!(lookup.query.contains("order=desc"));
+        assert!(lookup.query.contains("per_page=50"));
+        assert!(!lookup.query.contains("per_page=all"));
+
+        let campaign = api.clone().get_campaign_by_uuid("other").await.unwrap();
+        assert!(campaign.is_some());
+        assert_eq!(requests.find("GET /api/campaigns").len(), 1);
+        assert!(api.get_campaign_by_uuid("missing").await.unwrap().is_none());
+        assert_eq!(requests.find("GET /api/campaigns").len(), 2);
+        assert!(api.get_campaign_by_uuid("missing").await.unwrap().is_none());
+        assert_eq!(requests.find("GET /api/campaigns").len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_unsubscribe_from_lists() {
+        let (api, requests) = mock::listmonk(&[]);
+        api.unsubscribe_from_lists(&[3], &[4, 5]).await.unwrap();
+        assert_eq!(
+            requests.find("PUT /api/subscribers/lists")[0].body,
+            serde_json::json!({"ids": [3], "action": "unsubscribe", "target_list_ids": [4, 5]})
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_blocklist_subscribers() {
+        let (api, requests) = mock::listmonk(&[]);
+        api.blocklist_subscribers(&[3]).await.unwrap();
+        assert_eq!(
+            requests.find("PUT /api/subscribers/blocklist")[0].body,
+            serde_json::json!({"ids": [3]})
+        );
+    }
+
+    fn subscribers_response(email: &str) -> serde_json::Value {
+        serde_json::json!({
+            "data": {"results": [{"id": 3, "uuid": "6a9d1d7e", "email": email, "lists": []}]}
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_blocklist_by_email() {
+        let (api, requests) = mock::listmonk(&[(
+            "GET /api/subscribers",
+            subscribers_response("anon@example.com"),
+        )]);
+        api.blocklist_by_email(EmailAddress::from_parts(None, "anon@example.com"))
+            .await
+            .unwrap();
+        assert_eq!(
+            requests.find("PUT /api/subscribers/blocklist")[0].body,
+            serde_json::json!({"ids": [3]})
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_blocklist_by_email_escapes_malicious_input() {
+        let (api, requests) = mock::listmonk(&[(
+            "GET /api/subscribers",
+            serde_json::json!({"data": {"results": []}}),
+        )]);
+        api.blocklist_by_email(EmailAddress::from_parts(
+            None,
+            "x' OR subscribers.email LIKE '%@example.com",
+        ))
+        .await
+        .unwrap();
+        let query = actix_web::web::Query::<HashMap<String, String>>::from_query(
+            &requests.find("GET /api/subscribers")[0].query,
+        )
+        .unwrap();
+        assert_eq!(
+            query["query"],
+            "LOWER(subscribers.email) = 'x'' or subscribers.email like ''%@example.com'"
+        );
+        assert!(requests.find("PUT /api/subscribers/blocklist").is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_blocklist_by_email_requires_exact_match() {
+        let (api, requests) = mock::listmonk(&[(
+            "GET /api/subscribers",
+            subscribers_response("a1b@example.com"),
+        )]);
+        api.blocklist_by_email(EmailAddress::from_parts(None, "a_b@example.com"))
+            .await
+            .unwrap();
+        assert!(requests.find("PUT /api/subscribers/blocklist").is_empty());
     }
 }
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::mailersend::api::Attachment;
+
+/// MailerSend rejects messages whose attachments exceed 25 MB in total.
+pub const MAX_ATTACHMENTS_SIZE: usize = 25 * 1024 * 1024;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum AttachmentError {
+    #[error("Attachment {0} content is not valid base64")]
+    InvalidContent(String),
+
+    #[error("Attachments size {size} bytes exceeds the MailerSend limit of {max} bytes")]
+    TooLarge { size: usize, max: usize },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ListmonkAttachment {
+    name: String,
+    #[serde(default)]
+    header: Option<HashMap<String, Vec<String>>>,
+    content: String,
+}
+
+impl ListmonkAttachment {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.header
+            .as_ref()?
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, values)| values.first())
+            .map(|x| x.as_str())
+    }
+
+    /// Id the HTML refers to the attachment by, from its `Content-ID` header
+    /// and otherwise its name.
+    fn content_id(&self) -> String {
+        self.header("Content-ID")
+            .map(|x| x.trim().trim_start_matches('<').trim_end_matches('>'))
+            .filter(|x| !x.is_empty())
+            .unwrap_or(&self.name)
+            .to_string()
+    }
+}
+
+/// Converts listmonk attachments into MailerSend ones, validating their content
+/// and total size.
+pub fn to_mailersend(
+    attachments: &[ListmonkAttachment],
+) -> Result<Vec<Attachment>, AttachmentError> {
+    let mut size = 0;
+    let mut result = Vec::new();
+    for attachment in attachments {
+        let content = STANDARD
+            .decode(&attachment.content)
+            .map_err(|_| AttachmentError::InvalidContent(attachment.name.clone()))?;
+        size += content.len();
+        if size > MAX_ATTACHMENTS_SIZE {
+            return Err(AttachmentError::TooLarge {
+                size,
+                max: MAX_ATTACHMENTS_SIZE,
+            });
+        }
+        let inline = attachment
+            .header("Content-Disposition")
+            .is_some_and(|x| x.trim().to_lowercase().starts_with("inline"));
+        let (disposition, id) = if inline {
+            ("inline", Some(attachment.content_id()))
+        } else {
+            ("attachment", None)
+        };
+        result.push(Attachment {
+            content: attachment.content.clone(),
+            filename: attachment.name.clone(),
+            disposition: disposition.to_string(),
+            id,
+        });
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(name: &str, content: &[u8], disposition: Option<&str>) -> ListmonkAttachment {
+        ListmonkAttachment {
+            name: name.to_string(),
+            header: disposition
+                .map(|x| HashMap::from([("Content-Disposition".to_string(), vec![x.to_string()])])),
+            content: STANDARD.encode(content),
+        }
+    }
+
+    #[test]
+    fn test_parse_listmonk_attachment() {
+        let attachment: ListmonkAttachment = serde_json::from_str(
+            r#"{
+                "name": "invoice.pdf",
+                "header": {
+                    "Content-Type": ["application/pdf"],
+                    "Content-Disposition": ["attachment; filename=\"invoice.pdf\""]
+                },
+                "content": "aGVsbG8="
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            to_mailersend(&[attachment]).unwrap(),
+            vec![Attachment {
+                content: "aGVsbG8=".to_string(),
+                filename: "invoice.pdf".to_string(),
+                disposition: "attachment".to_string(),
+                id: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_inline_attachment() {
+        let attachments = to_mailersend(&[attachment(
+            "logo.png",
+            b"png",
+            Some("inline; filename=logo.png"),
+        )])
+        .unwrap();
+        assert_eq!(attachments[0].disposition, "inline");
+        assert_eq!(attachments[0].id.as_deref(), Some("logo.png"));
+    }
+
+    #[test]
+    fn test_inline_attachment_content_id() {
+        let mut inline = attachment("logo.png", b"png", Some("inline"));
+        inline.header.as_mut().unwrap().insert(
+            "Content-Id".to_string(),
+            vec!["<logo@listmonk>".to_string()],
+        );
+        let attachments = to_mailersend(&[inline]).unwrap();
+        assert_eq!(attachments[0].id.as_deref(), Some("logo@listmonk"));
+    }
+
+    #[test]
+    fn test_invalid_attachment_content() {
+        let mut invalid = attachment("invoice.pdf", b"pdf", None);
+        invalid.content = "not base64!".to_string();
+        assert_eq!(
+            to_mailersend(&[invalid]).unwrap_err(),
+            AttachmentError::InvalidContent("invoice.pdf".to_string())
+        );
+    }
+
+    #[test]
+    fn test_attachments_too_large() {
+        let half = vec![0u8; MAX_ATTACHMENTS_SIZE / 2 + 1];
+        let error = to_mailersend(&[
+            attachment("a.bin", &half, None),
+            attachment("b.bin", &half, None),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error,
+            AttachmentError::TooLarge {
+                size: MAX_ATTACHMENTS_SIZE + 2,
+                max: MAX_ATTACHMENTS_SIZE,
+            }
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use pulldown_cmark::{html, Options, Parser};
+
+const TEXT_WIDTH: usize = 80;
+
+#[derive(Debug, PartialEq)]
+pub struct Body {
+    pub html: Option<String>,
+    pub text: Option<String>,
+}
+
+/// Renders a listmonk campaign body into the HTML and plain text parts of
+/// an email according to its `content_type`.
+pub fn render(content_type: &str, body: &str) -> Body {
+    match content_type {
+        "plain" => Body {
+            html: None,
+            text: Some(body.to_string()),
+        },
+        "markdown" => Body {
+            html: Some(markdown_to_html(body)),
+            text: Some(body.to_string()),
+        },
+        _ => Body {
+            html: Some(body.to_string()),
+            text: Some(html_to_text(body)),
+        },
+    }
+}
+
+fn markdown_to_html(markdown: &str) -> String {
+    let parser = Parser::new_ext(markdown, Options::all());
+    let mut result = String::new();
+    html::push_html(&mut result, parser);
+    result
+}
+
+fn html_to_text(html: &str) -> String {
+    html2text::from_read(html.as_bytes(), TEXT_WIDTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_plain() {
+        assert_eq!(
+            render("plain", "The message body"),
+            Body {
+                html: None,
+                text: Some("The message body".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        let body = render("markdown", "# Hello\n\nSome **bold** text");
+        assert_eq!(
+            body.html,
+            Some("<h1>Hello</h1>\n<p>Some <strong>bold</strong> text</p>\n".to_string())
+        );
+        assert_eq!(body.text, Some("# Hello\n\nSome **bold** text".to_string()));
+    }
+
+    #[test]
+    fn test_render_html_generates_text_alternative() {
+        let html = "<html><body><h3>Hi Tomasz!</h3><p>Here is a <a href=\"https://listmonk.app\">link</a>.</p></body></html>";
+        for content_type in ["html", "richtext"] {
+            let body = render(content_type, html);
+            assert_eq!(body.html, Some(html.to_string()));
+            let text = body.text.unwrap();
+            assert!(text.contains("Hi Tomasz!"), "{}", text);
+            assert!(text.contains("https://listmonk.app"), "{}", text);
+            assert!(!text.contains("<p>"), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+
+use super::tracking::{CAMPAIGN_HEADER, SUBSCRIBER_HEADER};
+use crate::mailersend::api::Header;
+
+/// Headers MailerSend sets itself and which campaigns must not override.
+const RESERVED_HEADERS: &[&str] = &[
+    "bcc",
+    "cc",
+    "content-transfer-encoding",
+    "content-type",
+    "date",
+    "dkim-signature",
+    "from",
+    "message-id",
+    "mime-version",
+    "received",
+    "reply-to",
+    "return-path",
+    "sender",
+    "subject",
+    "to",
+];
+
+const LIST_UNSUBSCRIBE: &str = "List-Unsubscribe";
+const LIST_UNSUBSCRIBE_POST: &str = "List-Unsubscribe-Post";
+
+#[derive(Clone, Debug)]
+pub struct HeaderPolicy {
+    allowed: Vec<String>,
+    denied: Vec<String>,
+    listmonk_url: String,
+}
+
+impl HeaderPolicy {
+    /// An empty `allowed` list allows every header that is neither reserved
+    /// nor `denied`.
+    pub fn new(allowed: &[String], denied: &[String], listmonk_url: &str) -> Self {
+        let normalize = |names: &[String]| {
+            names
+                .iter()
+                .map(|x| x.trim().to_lowercase())
+                .filter(|x| !x.is_empty())
+                .collect()
+        };
+        HeaderPolicy {
+            allowed: normalize(allowed),
+            denied: normalize(denied),
+            listmonk_url: listmonk_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn is_allowed(&self, name: &str) -> bool {
+        if self.is_denied(name) {
+            return false;
+        }
+        self.allowed.is_empty() || self.allowed.contains(&name.trim().to_lowercase())
+    }
+
+    fn is_denied(&self, name: &str) -> bool {
+        let name = name.trim().to_lowercase();
+        RESERVED_HEADERS.contains(&name.as_str()) || self.denied.contains(&name)
+    }
+
+    /// Builds the custom headers of a message from the campaign headers, adding
+    /// one-click unsubscribe headers unless the campaign provides or denies them,
+    /// and the campaign and subscriber UUIDs unless denied.
+    pub fn headers(
+        &self,
+        campaign_headers: &[HashMap<String, String>],
+        campaign_uuid: &str,
+        subscriber_uuid: &str,
+    ) -> Vec<Header> {
+        let mut result: Vec<Header> = Vec::new();
+        for headers in campaign_headers {
+            let mut headers: Vec<(&String, &String)> = headers.iter().collect();
+            headers.sort();
+            for (name, value) in headers {
+                if !is_valid_header(name, value) {
+                    log::warn!("Skipping malformed campaign header {}", name);
+                    continue;
+                }
+                if !self.is_allowed(name) {
+                    log::warn!("Skipping disallowed campaign header {}", name);
+                    continue;
+                }
+                result.push(Header {
+                    name: name.trim().to_string(),
+                    value: value.trim().to_string(),
+                });
+            }
+        }
+        let has_unsubscribe = result
+            .iter()
+            .any(|x| x.name.eq_ignore_ascii_case(LIST_UNSUBSCRIBE));
+        if !has_unsubscribe && !self.is_denied(LIST_UNSUBSCRIBE) {
+            result.push(Header {
+                name: LIST_UNSUBSCRIBE.to_string(),
+                value: format!(
+                    "<{}/subscription/{}/{}>",
+                    self.listmonk_url, campaign_uuid, subscriber_uuid
+                ),
+            });
+            let has_unsubscribe_post = result
+                .iter()
+                .any(|x| x.name.eq_ignore_ascii_case(LIST_UNSUBSCRIBE_POST));
+            if !has_unsubscribe_post && !self.is_denied(LIST_UNSUBSCRIBE_POST) {
+                result.push(Header {
+                    name: LIST_UNSUBSCRIBE_POST.to_string(),
+                    value: "List-Unsubscribe=One-Click".to_string(),
+                });
+            }
+        }
+        for (name, value) in [
+            (CAMPAIGN_HEADER, campaign_uuid),
+            (SUBSCRIBER_HEADER, subscriber_uuid),
+        ] {
+            result.retain(|x| !x.name.eq_ignore_ascii_case(name));
+            if !self.is_denied(name) {
+                result.push(Header {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                });
+            }
+        }
+        result
+    }
+}
+
+fn is_valid_header(name: &str, value: &str) -> bool {
+    !name.trim().is_empty()
+        && name.chars().all(|x| x.is_ascii_graphic() && x != ':')
+        && !value.contains(['\r', '\n'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn campaign_headers(headers: &[(&str, &str)]) -> Vec<HashMap<String, String>> {
+        headers
+            .iter()
+            .map(|(name, value)| HashMap::from([(name.to_string(), value.to_string())]))
+            .collect()
+    }
+
+    fn names(headers: &[Header]) -> Vec<&str> {
+        headers.iter().map(|x| x.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_campaign_headers_are_forwarded() {
+        let policy = HeaderPolicy::new(&[], &[], "https://newsletter.devdot.blog/");
+        let headers = policy.headers(
+            &campaign_headers(&[("X-CampaignUUID", "edaf1cc0")]),
+            "a4d516f2",
+            "1db7ee2a",
+        );
+        assert_eq!(
+            headers,
+            vec![
+                Header {
+                    name: "X-CampaignUUID".to_string(),
+                    value: "edaf1cc0".to_string(),
+                },
+                Header {
+                    name: "List-Unsubscribe".to_string(),
+                    value: "<https://newsletter.devdot.blog/subscription/a4d516f2/1db7ee2a>"
+                        .to_string(),
+                },
+                Header {
+                    name: "List-Unsubscribe-Post".to_string(),
+                    value: "List-Unsubscribe=One-Click".to_string(),
+                },
+                Header {
+                    name: "X-Listmonk-Campaign".to_string(),
+                    value: "a4d516f2".to_string(),
+                },
+                Header {
+                    name: "X-Listmonk-Subscriber".to_string(),
+                    value: "1db7ee2a".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reserved_and_denied_headers_are_skipped() {
+        let policy = HeaderPolicy::new(
+            &[],
+            &[
+                "x-internal".to_string(),
+                "x-listmonk-subscriber".to_string(),
+            ],
+            "http://listmonk",
+        );
+        let headers = policy.headers(
+            &campaign_headers(&[
+                ("From", "attacker@email.com"),
+                ("reply-to", "attacker@email.com"),
+                ("X-Internal", "secret"),
+                ("X-Mailer", "listmonk"),
+            ]),
+            "campaign",
+            "subscriber",
+        );
+        assert_eq!(
+            names(&headers),
+            vec![
+                "X-Mailer",
+                "List-Unsubscribe",
+                "List-Unsubscribe-Post",
+                "X-Listmonk-Campaign"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let policy = HeaderPolicy::new(&["X-Mailer".to_string()], &[], "http://listmonk");
+        let headers = policy.headers(
+            &campaign_headers(&[("X-Mailer", "listmonk"), ("X-Other", "value")]),
+            "campaign",
+            "subscriber",
+        );
+        assert_eq!(
+            names(&headers),
+            vec![
+                "X-Mailer",
+                "List-Unsubscribe",
+                "List-Unsubscribe-Post",
+                "X-Listmonk-Campaign",
+                "X-Listmonk-Subscriber"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_campaign_unsubscribe_header_is_kept() {
+        let policy = HeaderPolicy::new(&[], &[], "http://listmonk");
+        let headers = policy.headers(
+            &campaign_headers(&[("list-unsubscribe", "<mailto:unsubscribe@email.com>")]),
+            "campaign",
+            "subscriber",
+        );
+        assert_eq!(
+            names(&headers),
+            vec![
+                "list-unsubscribe",
+                "X-Listmonk-Campaign",
+                "X-Listmonk-Subscriber"
+            ]
+        );
+        assert_eq!(headers[0].value, "<mailto:unsubscribe@email.com>");
+    }
+
+    #[test]
+    fn test_malformed_headers_are_skipped() {
+        let policy = HeaderPolicy::new(
+            &[],
+            &[
+                "list-unsubscribe".to_string(),
+                "x-listmonk-campaign".to_string(),
+                "x-listmonk-subscriber".to_string(),
+            ],
+            "http://listmonk",
+        );
+        let headers = policy.headers(
+            &campaign_headers(&[
+                ("X-Injected", "value\r\nBcc: attacker@email.com"),
+                ("X Space", "value"),
+            ]),
+            "campaign",
+            "subscriber",
+        );
+        assert!(headers.is_empty());
+    }
+}
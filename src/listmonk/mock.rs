@@ -0,0 +1,83 @@
+//! A local HTTP server standing in for the listmonk API in tests.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+
+use super::api::ListmonkAPI;
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub body: serde_json::Value,
+}
+
+#[derive(Clone, Default)]
+pub struct Requests(Arc<Mutex<Vec<RecordedRequest>>>);
+
+impl Requests {
+    pub fn all(&self) -> Vec<RecordedRequest> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Requests with the given method and path, e.g. `PUT /api/subscribers/lists`.
+    pub fn find(&self, route: &str) -> Vec<RecordedRequest> {
+        self.all()
+            .into_iter()
+            .filter(|x| format!("{} {}", x.method, x.path) == route)
+            .collect()
+    }
+
+    pub fn record(&self, request: &HttpRequest, body: &[u8]) {
+        self.0.lock().unwrap().push(RecordedRequest {
+            method: request.method().to_string(),
+            path: request.path().to_string(),
+            query: request.query_string().to_string(),
+            body: serde_json::from_slice(body).unwrap_or(serde_json::Value::Null),
+        });
+    }
+}
+
+type Responses = HashMap<String, serde_json::Value>;
+
+async fn respond(
+    request: HttpRequest,
+    body: web::Bytes,
+    requests: web::Data<Requests>,
+    responses: web::Data<Responses>,
+) -> HttpResponse {
+    let route = format!("{} {}", request.method(), request.path());
+    requests.record(&request, &body);
+    match responses.get(&route) {
+        Some(response) => HttpResponse::Ok().json(response),
+        None => HttpResponse::Ok().json(serde_json::json!({ "data": true })),
+    }
+}
+
+/// Starts a server answering each `METHOD /path` route with the given JSON,
+/// and `{"data": true}` otherwise.
+pub fn listmonk(responses: &[(&str, serde_json::Value)]) -> (ListmonkAPI, Requests) {
+    let requests = Requests::default();
+    let responses: Responses = responses
+        .iter()
+        .map(|(route, response)| (route.to_string(), response.clone()))
+        .collect();
+    let server_requests = requests.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(server_requests.clone()))
+            .app_data(web::Data::new(responses.clone()))
+            .default_service(web::to(respond))
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let address = server.addrs()[0];
+    actix_rt::spawn(server.run());
+    (
+        ListmonkAPI::new(&format!("http://{}", address), "user", "password"),
+        requests,
+    )
+}
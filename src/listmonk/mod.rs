@@ -1,2 +1,10 @@
 pub mod api;
+pub mod attachments;
+pub mod content;
+pub mod headers;
+#[cfg(test)]
+pub mod mock;
 pub mod rest;
+pub mod sender;
+pub mod template;
+pub mod tracking;
@@ -1,8 +1,15 @@
 use std::collections::HashMap;
 
-use crate::mailersend::api::{Email, EmailAddress};
+use super::attachments::{self, ListmonkAttachment, MAX_ATTACHMENTS_SIZE};
+use super::content;
+use super::headers::HeaderPolicy;
+use super::sender::SenderPolicy;
+use super::template;
+use super::tracking::{self, TrackingPolicy};
+use crate::error::RestError;
+use crate::mailersend::api::{Email, EmailAddress, Personalization, MAX_TAGS};
 use crate::mailersend::buffer::Buffer;
-use actix_web::{web, HttpResponse, Responder, Result};
+use actix_web::{web, HttpRequest, HttpResponse};
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -10,14 +17,32 @@ pub struct Recipient {
     uuid: String,
     email: String,
     name: Option<String>,
+    #[serde(default)]
+    attribs: serde_json::Map<String, serde_json::Value>,
     status: String,
 }
 
+impl Recipient {
+    /// Subscriber data available to MailerSend templates as `{{ var }}`.
+    fn personalization(&self) -> Personalization {
+        Personalization {
+            email: self.email.clone(),
+            data: serde_json::json!({
+                "name": self.name.clone().unwrap_or_default(),
+                "email": self.email,
+                "uuid": self.uuid,
+                "attribs": self.attribs,
+            }),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Campaign {
     uuid: String,
     name: String,
     from_email: String,
+    #[serde(default)]
     headers: Vec<HashMap<String, String>>,
     tags: Option<Vec<String>>,
 }
@@ -29,6 +54,22 @@ pub struct MessengerRequest {
     content_type: String,
     recipients: Vec<Recipient>,
     campaign: Campaign,
+    #[serde(default)]
+    attachments: Option<Vec<ListmonkAttachment>>,
+}
+
+/// Base64 encoded attachments take a third more space than their content.
+pub const MAX_MESSENGER_REQUEST_SIZE: usize = MAX_ATTACHMENTS_SIZE / 3 * 4 + 8 * 1024 * 1024;
+
+/// JSON body settings of the messenger endpoint. Bodies that cannot be read
+/// are answered with a messenger error like any other invalid request.
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(MAX_MESSENGER_REQUEST_SIZE)
+        .error_handler(|err, _: &HttpRequest| {
+            log::warn!("Rejecting invalid messenger request: {}", err);
+            RestError::from(err).into()
+        })
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -38,74 +79,157 @@ pub struct MessengerResponse {
     data: Option<String>,
 }
 
+impl MessengerResponse {
+    pub fn success(message: Option<String>) -> Self {
+        MessengerResponse {
+            status: "success".to_string(),
+            message,
+            data: None,
+        }
+    }
+
+    pub fn error(message: &str) -> Self {
+        MessengerResponse {
+            status: "error".to_string(),
+            message: Some(message.to_string()),
+            data: None,
+        }
+    }
+}
+
 pub async fn messenger_handler(
     email_buffer: web::Data<Buffer>,
+    header_policy: web::Data<HeaderPolicy>,
+    sender_policy: web::Data<SenderPolicy>,
+    tracking_policy: web::Data<TrackingPolicy>,
     messenger_req: web::Json<MessengerRequest>,
-) -> Result<impl Responder> {
+) -> Result<HttpResponse, RestError> {
     log::info!("Received messenger request: {:?}", messenger_req);
-    let mut tags = messenger_req.campaign.tags.clone().unwrap_or_default();
-    tags.push(format!("campaign:{}", messenger_req.campaign.uuid));
-    let from_address =
-        EmailAddress::from_string(&messenger_req.campaign.from_email).expect("Invalid from email");
-    let emails = messenger_req
-        .recipients
+    let campaign_tags = messenger_req.campaign.tags.clone().unwrap_or_default();
+    let template_id = template::template_id(&campaign_tags, &messenger_req.campaign.headers);
+    let mut tags: Vec<String> = campaign_tags
+        .into_iter()
+        .filter(|x| !template::is_template_tag(x))
+        .collect();
+    if tags.len() >= MAX_TAGS {
+        log::warn!(
+            "Campaign {} has more than {} tags, keeping the first ones: {:?}",
+            messenger_req.campaign.uuid,
+            MAX_TAGS - 1,
+            tags
+        );
+        tags.truncate(MAX_TAGS - 1);
+    }
+    tags.push(tracking::campaign_tag(&messenger_req.campaign.uuid));
+    let from_email = &messenger_req.campaign.from_email;
+    let from_address = EmailAddress::from_string(from_email)
+        .map_err(|_| RestError::InvalidSender(from_email.clone()))?;
+    let sender = sender_policy
+        .sender(from_address)
+        .ok_or_else(|| RestError::SenderNotAllowed(from_email.clone()))?;
+    let body = match template_id {
+        Some(_) => content::Body {
+            html: None,
+            text: None,
+        },
+        None => content::render(&messenger_req.content_type, &messenger_req.body),
+    };
+    let html = tracking_policy.html(body.html);
+    let campaign_headers: Vec<HashMap<String, String>> = messenger_req
+        .campaign
+        .headers
         .iter()
-        .filter(|recipient| {
-            if recipient.status == "enabled" {
-                return true;
-            }
-            log::info!(
-                "Recipient {} is not enabled, skipping",
-                recipient.email.clone()
-            );
-            false
+        .map(|headers| {
+            headers
+                .iter()
+                .filter(|(name, _)| !template::is_template_header(name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect()
         })
-        .map(|recipient| Email {
-            from: from_address.clone(),
+        .collect();
+    let attachments =
+        attachments::to_mailersend(messenger_req.attachments.as_deref().unwrap_or_default())?;
+    let mut skipped = Vec::new();
+    let mut emails = Vec::new();
+    for recipient in &messenger_req.recipients {
+        if recipient.status != "enabled" {
+            log::info!("Recipient {} is not enabled, skipping", recipient.email);
+            continue;
+        }
+        if EmailAddress::from_string(&recipient.email).is_err() {
+            let e = RestError::InvalidRecipient(recipient.email.clone());
+            log::warn!("Skipping recipient {}: {}", recipient.uuid, e);
+            skipped.push(e.to_string());
+            continue;
+        }
+        emails.push(Email {
+            from: sender.from.clone(),
             to: vec![EmailAddress::from_parts(
                 recipient.name.clone(),
                 &recipient.email,
             )],
-            reply_to: None,
+            reply_to: sender.reply_to.clone(),
             subject: messenger_req.subject.clone(),
-            text: None,
-            html: Some(messenger_req.body.clone()),
+            text: body.text.clone(),
+            html: html.clone(),
+            template_id: template_id.clone(),
             tags: tags.clone(),
-        })
-        .collect();
-    email_buffer.push_all(emails).await;
-    Ok(HttpResponse::Ok())
+            headers: header_policy.headers(
+                &campaign_headers,
+                &messenger_req.campaign.uuid,
+                &recipient.uuid,
+            ),
+            attachments: attachments.clone(),
+            personalization: vec![recipient.personalization()],
+        });
+    }
+    if emails.is_empty() && !skipped.is_empty() {
+        return Err(RestError::NoValidRecipients);
+    }
+    email_buffer.push_all(emails).await?;
+    let message = if skipped.is_empty() {
+        None
+    } else {
+        Some(skipped.join("; "))
+    };
+    Ok(HttpResponse::Ok().json(MessengerResponse::success(message)))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::mailersend::api::EmailAddress;
+    use crate::mailersend::api::{EmailAddress, Header};
+    use crate::storage::Database;
+    use actix_web::{http::StatusCode, test as actix_test, App, ResponseError};
 
     #[actix_rt::test]
     async fn test_messenger_handler() {
-        let email_buffer = web::Data::new(Buffer::new());
+        let email_buffer =
+            web::Data::new(Buffer::new(Database::open_in_memory().unwrap()).unwrap());
         let messenger_req = web::Json(MessengerRequest {
             subject: "Test subject".to_string(),
             body: "<h1>Test</h1>".to_string(),
-            content_type: "text/html".to_string(),
+            content_type: "html".to_string(),
             recipients: vec![
                 Recipient {
                     uuid: "123".to_string(),
                     email: "test@email.com".to_string(),
                     name: None,
+                    attribs: serde_json::Map::new(),
                     status: "enabled".to_string(),
                 },
                 Recipient {
                     uuid: "456".to_string(),
                     email: "test2@email.com".to_string(),
                     name: Some("Test recipient".to_string()),
+                    attribs: serde_json::Map::new(),
                     status: "enabled".to_string(),
                 },
                 Recipient {
                     uuid: "156".to_string(),
                     email: "test3@email.com".to_string(),
                     name: Some("Test recipient".to_string()),
+                    attribs: serde_json::Map::new(),
                     status: "blocklisted".to_string(),
                 },
             ],
@@ -113,14 +237,33 @@ mod tests {
                 uuid: "789".to_string(),
                 name: "Test campaign".to_string(),
                 from_email: "from@email.com".to_string(),
-                headers: vec![],
+                headers: vec![HashMap::from([(
+                    "X-CampaignUUID".to_string(),
+                    "789".to_string(),
+                )])],
                 tags: None,
             },
+            attachments: None,
         });
-        messenger_handler(email_buffer.clone(), messenger_req)
+        let header_policy = web::Data::new(HeaderPolicy::new(&[], &[], "http://listmonk"));
+        let sender_policy = web::Data::new(SenderPolicy::new(&[], None));
+        let tracking_policy = web::Data::new(TrackingPolicy::new(false));
+        messenger_handler(
+            email_buffer.clone(),
+            header_policy,
+            sender_policy,
+            tracking_policy,
+            messenger_req,
+        )
+        .await
+        .unwrap();
+        let emails: Vec<Email> = email_buffer
+            .pop_all()
             .await
-            .unwrap();
-        let emails = email_buffer.pop_all().await;
+            .unwrap()
+            .into_iter()
+            .map(|x| x.email)
+            .collect();
         assert_eq!(emails.len(), 2);
         assert_eq!(
             emails[0].from,
@@ -134,9 +277,401 @@ mod tests {
         );
         assert_eq!(emails[0].reply_to, None);
         assert_eq!(emails[0].subject, "Test subject".to_string());
-        assert_eq!(emails[0].text, None);
+        assert_eq!(emails[0].text, Some("# Test\n".to_string()));
         assert_eq!(emails[0].html, Some("<h1>Test</h1>".to_string()));
-        assert_eq!(emails[0].tags.len(), 1);
-        assert_eq!(emails[0].tags[0], "campaign:789".to_string());
+        assert!(emails[0].attachments.is_empty());
+        assert_eq!(
+            emails[0].personalization,
+            vec![Personalization {
+                email: "test@email.com".to_string(),
+                data: serde_json::json!({
+                    "name": "",
+                    "email": "test@email.com",
+                    "uuid": "123",
+                    "attribs": {},
+                }),
+            }]
+        );
+        assert_eq!(emails[0].tags, vec!["campaign:789".to_string()]);
+        assert_eq!(
+            emails[0].headers,
+            vec![
+                Header {
+                    name: "X-CampaignUUID".to_string(),
+                    value: "789".to_string(),
+                },
+                Header {
+                    name: "List-Unsubscribe".to_string(),
+                    value: "<http://listmonk/subscription/789/123>".to_string(),
+                },
+                Header {
+                    name: "List-Unsubscribe-Post".to_string(),
+                    value: "List-Unsubscribe=One-Click".to_string(),
+                },
+                Header {
+                    name: "X-Listmonk-Campaign".to_string(),
+                    value: "789".to_string(),
+                },
+                Header {
+                    name: "X-Listmonk-Subscriber".to_string(),
+                    value: "123".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_messenger_handler_rejects_invalid_json() {
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(json_config())
+                .app_data(web::Data::new(
+                    Buffer::new(Database::open_in_memory().unwrap()).unwrap(),
+                ))
+                .app_data(web::Data::new(HeaderPolicy::new(
+                    &[],
+                    &[],
+                    "http://listmonk",
+                )))
+                .app_data(web::Data::new(SenderPolicy::new(&[], None)))
+                .app_data(web::Data::new(TrackingPolicy::new(false)))
+                .route("/api/messenger", web::post().to(messenger_handler)),
+        )
+        .await;
+        let request = actix_test::TestRequest::post()
+            .uri("/api/messenger")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload("{\"subject\": ")
+            .to_request();
+        let response = actix_test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = actix_test::read_body_json(response).await;
+        assert_eq!(body["status"], "error");
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid request"));
+    }
+
+    #[actix_rt::test]
+    async fn test_messenger_handler_with_attachments() {
+        let email_buffer =
+            web::Data::new(Buffer::new(Database::open_in_memory().unwrap()).unwrap());
+        let header_policy = web::Data::new(HeaderPolicy::new(&[], &[], "http://listmonk"));
+        let sender_policy = web::Data::new(SenderPolicy::new(&[], None));
+        let tracking_policy = web::Data::new(TrackingPolicy::new(false));
+        let mut messenger_req: MessengerRequest =
+            serde_json::from_str(include_str!("../../test/req2.json")).unwrap();
+        messenger_req.attachments = Some(
+            serde_json::from_str(r#"[{"name": "hello.txt", "header": {}, "content": "aGVsbG8="}]"#)
+                .unwrap(),
+        );
+        let response = messenger_handler(
+            email_buffer.clone(),
+            header_policy,
+            sender_policy,
+            tracking_policy,
+            web::Json(messenger_req),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let emails = email_buffer.pop_all().await.unwrap();
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0].email.attachments.len(), 1);
+        assert_eq!(emails[0].email.attachments[0].filename, "hello.txt");
+    }
+
+    #[actix_rt::test]
+    async fn test_messenger_handler_rejects_invalid_attachments() {
+        let email_buffer =
+            web::Data::new(Buffer::new(Database::open_in_memory().unwrap()).unwrap());
+        let header_policy = web::Data::new(HeaderPolicy::new(&[], &[], "http://listmonk"));
+        let sender_policy = web::Data::new(SenderPolicy::new(&[], None));
+        let tracking_policy = web::Data::new(TrackingPolicy::new(false));
+        let mut messenger_req: MessengerRequest =
+            serde_json::from_str(include_str!("../../test/req2.json")).unwrap();
+        messenger_req.attachments = Some(
+            serde_json::from_str(r#"[{"name": "hello.txt", "header": {}, "content": "%%%"}]"#)
+                .unwrap(),
+        );
+        let error = messenger_handler(
+            email_buffer.clone(),
+            header_policy,
+            sender_policy,
+            tracking_policy,
+            web::Json(messenger_req),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+        assert!(email_buffer.pop_all().await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_messenger_handler_rejects_too_large_attachments() {
+        let email_buffer =
+            web::Data::new(Buffer::new(Database::open_in_memory().unwrap()).unwrap());
+        let header_policy = web::Data::new(HeaderPolicy::new(&[], &[], "http://listmonk"));
+        let sender_policy = web::Data::new(SenderPolicy::new(&[], None));
+        let tracking_policy = web::Data::new(TrackingPolicy::new(false));
+        let mut messenger_req: MessengerRequest =
+            serde_json::from_str(include_str!("../../test/req2.json")).unwrap();
+        let content = "A".repeat(MAX_ATTACHMENTS_SIZE / 3 * 4 + 4);
+        messenger_req.attachments = Some(
+            serde_json::from_value(serde_json::json!([
+                {"name": "large.bin", "header": {}, "content": content}
+            ]))
+            .unwrap(),
+        );
+        let error = messenger_handler(
+            email_buffer.clone(),
+            header_policy,
+            sender_policy,
+            tracking_policy,
+            web::Json(messenger_req),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(email_buffer.pop_all().await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_messenger_handler_with_template() {
+        let email_buffer =
+            web::Data::new(Buffer::new(Database::open_in_memory().unwrap()).unwrap());
+        let header_policy = web::Data::new(HeaderPolicy::new(&[], &[], "http://listmonk"));
+        let sender_policy = web::Data::new(SenderPolicy::new(&[], None));
+        let tracking_policy = web::Data::new(TrackingPolicy::new(false));
+        let mut messenger_req: MessengerRequest =
+            serde_json::from_str(include_str!("../../test/req2.json")).unwrap();
+        messenger_req.campaign.tags = Some(vec![
+            "newsletter".to_string(),
+            "mailersend-template:0z76k5jg0o3yeg2d".to_string(),
+        ]);
+        messenger_handler(
+            email_buffer.clone(),
+            header_policy,
+            sender_policy,
+            tracking_policy,
+            web::Json(messenger_req),
+        )
+        .await
+        .unwrap();
+        let emails = email_buffer.pop_all().await.unwrap();
+        let email = &emails[0].email;
+        assert_eq!(email.template_id, Some("0z76k5jg0o3yeg2d".to_string()));
+        assert_eq!(email.html, None);
+        assert_eq!(email.text, None);
+        assert_eq!(email.personalization.len(), 1);
+        assert!(email.tags.contains(&"newsletter".to_string()));
+        assert!(!email.tags.iter().any(|x| template::is_template_tag(x)));
+    }
+
+    #[actix_rt::test]
+    async fn test_messenger_handler_limits_tags() {
+        let email_buffer =
+            web::Data::new(Buffer::new(Database::open_in_memory().unwrap()).unwrap());
+        let header_policy = web::Data::new(HeaderPolicy::new(&[], &[], "http://listmonk"));
+        let sender_policy = web::Data::new(SenderPolicy::new(&[], None));
+        let tracking_policy = web::Data::new(TrackingPolicy::new(false));
+        let mut messenger_req: MessengerRequest =
+            serde_json::from_str(include_str!("../../test/req2.json")).unwrap();
+        let campaign_tags: Vec<String> = (1..=5).map(|x| format!("tag{}", x)).collect();
+        messenger_req.campaign.tags = Some(campaign_tags);
+        let campaign_uuid = messenger_req.campaign.uuid.clone();
+        messenger_handler(
+            email_buffer.clone(),
+            header_policy,
+            sender_policy,
+            tracking_policy,
+            web::Json(messenger_req),
+        )
+        .await
+        .unwrap();
+        let emails = email_buffer.pop_all().await.unwrap();
+        let email = &emails[0].email;
+        assert_eq!(
+            email.tags,
+            vec![
+                "tag1",
+                "tag2",
+                "tag3",
+                "tag4",
+                &format!("campaign:{}", campaign_uuid)
+            ]
+        );
+        assert!(email
+            .headers
+            .iter()
+            .any(|x| x.name == tracking::SUBSCRIBER_HEADER));
+    }
+
+    #[actix_rt::test]
+    async fn test_messenger_handler_with_invalid_template() {
+        let email_buffer =
+            web::Data::new(Buffer::new(Database::open_in_memory().unwrap()).unwrap());
+        let header_policy = web::Data::new(HeaderPolicy::new(&[], &[], "http://listmonk"));
+        let sender_policy = web::Data::new(SenderPolicy::new(&[], None));
+        let tracking_policy = web::Data::new(TrackingPolicy::new(false));
+        let mut messenger_req: MessengerRequest =
+            serde_json::from_str(include_str!("../../test/req2.json")).unwrap();
+        messenger_req.campaign.headers = vec![HashMap::from([(
+            "X-MailerSend-Template".to_string(),
+            "not a template".to_string(),
+        )])];
+        messenger_handler(
+            email_buffer.clone(),
+            header_policy,
+            sender_policy,
+            tracking_policy,
+            web::Json(messenger_req),
+        )
+        .await
+        .unwrap();
+        let emails = email_buffer.pop_all().await.unwrap();
+        let email = &emails[0].email;
+        assert_eq!(email.template_id, None);
+        assert!(email.html.is_some());
+        assert!(!email
+            .headers
+            .iter()
+            .any(|x| template::is_template_header(&x.name)));
+    }
+
+    #[actix_rt::test]
+    async fn test_messenger_handler_rewrites_disallowed_sender() {
+        let email_buffer =
+            web::Data::new(Buffer::new(Database::open_in_memory().unwrap()).unwrap());
+        let header_policy = web::Data::new(HeaderPolicy::new(&[], &[], "http://listmonk"));
+        let sender_policy = web::Data::new(SenderPolicy::new(
+            &["devdot.blog".to_string()],
+            Some(EmailAddress::from_string("news@devdot.blog").unwrap()),
+        ));
+        let tracking_policy = web::Data::new(TrackingPolicy::new(false));
+        let mut messenger_req: MessengerRequest =
+            serde_json::from_str(include_str!("../../test/req2.json")).unwrap();
+        messenger_req.campaign.from_email = "John <john@gmail.com>".to_string();
+        messenger_handler(
+            email_buffer.clone(),
+            header_policy,
+            sender_policy,
+            tracking_policy,
+            web::Json(messenger_req),
+        )
+        .await
+        .unwrap();
+        let emails = email_buffer.pop_all().await.unwrap();
+        assert_eq!(emails[0].email.from.email(), "news@devdot.blog");
+        assert_eq!(
+            emails[0].email.reply_to,
+            Some(EmailAddress::from_string("John <john@gmail.com>").unwrap())
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_messenger_handler_rejects_disallowed_sender() {
+        let email_buffer =
+            web::Data::new(Buffer::new(Database::open_in_memory().unwrap()).unwrap());
+        let header_policy = web::Data::new(HeaderPolicy::new(&[], &[], "http://listmonk"));
+        let sender_policy = web::Data::new(SenderPolicy::new(&["devdot.blog".to_string()], None));
+        let tracking_policy = web::Data::new(TrackingPolicy::new(false));
+        let mut messenger_req: MessengerRequest =
+            serde_json::from_str(include_str!("../../test/req2.json")).unwrap();
+        messenger_req.campaign.from_email = "john@gmail.com".to_string();
+        let error = messenger_handler(
+            email_buffer.clone(),
+            header_policy,
+            sender_policy,
+            tracking_policy,
+            web::Json(messenger_req),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+        assert!(email_buffer.pop_all().await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_messenger_handler_rejects_invalid_sender() {
+        let email_buffer =
+            web::Data::new(Buffer::new(Database::open_in_memory().unwrap()).unwrap());
+        let header_policy = web::Data::new(HeaderPolicy::new(&[], &[], "http://listmonk"));
+        let sender_policy = web::Data::new(SenderPolicy::new(&[], None));
+        let tracking_policy = web::Data::new(TrackingPolicy::new(false));
+        let mut messenger_req: MessengerRequest =
+            serde_json::from_str(include_str!("../../test/req2.json")).unwrap();
+        messenger_req.campaign.from_email = "not-an-email".to_string();
+        let error = messenger_handler(
+            email_buffer.clone(),
+            header_policy,
+            sender_policy,
+            tracking_policy,
+            web::Json(messenger_req),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, RestError::InvalidSender(_)));
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_messenger_handler_skips_invalid_recipients() {
+        let email_buffer =
+            web::Data::new(Buffer::new(Database::open_in_memory().unwrap()).unwrap());
+        let header_policy = web::Data::new(HeaderPolicy::new(&[], &[], "http://listmonk"));
+        let sender_policy = web::Data::new(SenderPolicy::new(&[], None));
+        let tracking_policy = web::Data::new(TrackingPolicy::new(false));
+        let mut messenger_req: MessengerRequest =
+            serde_json::from_str(include_str!("../../test/req2.json")).unwrap();
+        messenger_req.recipients.push(Recipient {
+            uuid: "999".to_string(),
+            email: "not-an-email".to_string(),
+            name: None,
+            attribs: serde_json::Map::new(),
+            status: "enabled".to_string(),
+        });
+        let response = messenger_handler(
+            email_buffer.clone(),
+            header_policy.clone(),
+            sender_policy.clone(),
+            tracking_policy.clone(),
+            web::Json(messenger_req),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(email_buffer.pop_all().await.unwrap().len(), 1);
+
+        let mut messenger_req: MessengerRequest =
+            serde_json::from_str(include_str!("../../test/req2.json")).unwrap();
+        messenger_req.recipients[0].email = "not-an-email".to_string();
+        let error = messenger_handler(
+            email_buffer.clone(),
+            header_policy,
+            sender_policy,
+            tracking_policy,
+            web::Json(messenger_req),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, RestError::NoValidRecipients));
+    }
+
+    #[test]
+    fn test_recipient_personalization() {
+        let request: serde_json::Value =
+            serde_json::from_str(include_str!("../../test/req.json")).unwrap();
+        let recipient: Recipient =
+            serde_json::from_value(request["recipients"][0].clone()).unwrap();
+        let personalization = recipient.personalization();
+        assert_eq!(personalization.email, "anon@example.com");
+        assert_eq!(personalization.data["name"], "Anon Doe");
+        assert_eq!(
+            personalization.data["uuid"],
+            "e44b4135-1e1d-40c5-8a30-0f9a886c2884"
+        );
+        assert_eq!(personalization.data["attribs"]["city"], "Bengaluru");
     }
 }
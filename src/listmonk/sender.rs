@@ -0,0 +1,113 @@
+use crate::mailersend::api::EmailAddress;
+
+#[derive(Clone, Debug)]
+pub struct SenderPolicy {
+    allowed: Vec<String>,
+    default_from: Option<EmailAddress>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Sender {
+    pub from: EmailAddress,
+    pub reply_to: Option<EmailAddress>,
+}
+
+impl SenderPolicy {
+    /// `allowed` holds sender domains or full addresses. An empty list
+    /// allows every sender.
+    pub fn new(allowed: &[String], default_from: Option<EmailAddress>) -> Self {
+        SenderPolicy {
+            allowed: allowed
+                .iter()
+                .map(|x| x.trim().to_lowercase())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            default_from,
+        }
+    }
+
+    pub fn is_allowed(&self, address: &EmailAddress) -> bool {
+        if self.allowed.is_empty() {
+            return true;
+        }
+        let email = address.email().to_lowercase();
+        let domain = address.domain().to_lowercase();
+        self.allowed.iter().any(|x| *x == email || *x == domain)
+    }
+
+    /// Keeps allowed senders and rewrites the others to the default From,
+    /// replying to the original sender. Returns `None` when the sender is
+    /// not allowed and there is no default From.
+    pub fn sender(&self, from: EmailAddress) -> Option<Sender> {
+        if self.is_allowed(&from) {
+            return Some(Sender {
+                from,
+                reply_to: None,
+            });
+        }
+        let default_from = self.default_from.clone()?;
+        log::warn!(
+            "Sender {} is not allowed, sending from {} instead",
+            from.email(),
+            default_from.email()
+        );
+        Some(Sender {
+            from: default_from,
+            reply_to: Some(from),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(email: &str) -> EmailAddress {
+        EmailAddress::from_string(email).unwrap()
+    }
+
+    #[test]
+    fn test_allowed_domains_and_addresses() {
+        let policy = SenderPolicy::new(
+            &["devdot.blog".to_string(), "Team@Other.com".to_string()],
+            None,
+        );
+        assert!(policy.is_allowed(&address("news@DevDot.blog")));
+        assert!(policy.is_allowed(&address("team@other.com")));
+        assert!(!policy.is_allowed(&address("other@other.com")));
+        assert!(!policy.is_allowed(&address("news@sub.devdot.blog")));
+    }
+
+    #[test]
+    fn test_empty_allow_list_allows_every_sender() {
+        let policy = SenderPolicy::new(&[], None);
+        assert_eq!(
+            policy.sender(address("news@email.com")),
+            Some(Sender {
+                from: address("news@email.com"),
+                reply_to: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_disallowed_sender_is_rewritten() {
+        let policy = SenderPolicy::new(
+            &["devdot.blog".to_string()],
+            Some(address("Newsletter <news@devdot.blog>")),
+        );
+        assert_eq!(
+            policy.sender(address("John <john@gmail.com>")),
+            Some(Sender {
+                from: address("Newsletter <news@devdot.blog>"),
+                reply_to: Some(address("John <john@gmail.com>")),
+            })
+        );
+    }
+
+    #[test]
+    fn test_disallowed_sender_without_default() {
+        let policy = SenderPolicy::new(&["devdot.blog".to_string()], None);
+        assert_eq!(policy.sender(address("john@gmail.com")), None);
+    }
+}
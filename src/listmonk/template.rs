@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+pub const TEMPLATE_TAG_PREFIX: &str = "mailersend-template:";
+pub const TEMPLATE_HEADER: &str = "X-MailerSend-Template";
+
+lazy_static! {
+    static ref TEMPLATE_ID_REGEX: Regex = Regex::new(r"^[a-z0-9]{8,32}$").unwrap();
+}
+
+pub fn is_template_tag(tag: &str) -> bool {
+    tag.starts_with(TEMPLATE_TAG_PREFIX)
+}
+
+pub fn is_template_header(name: &str) -> bool {
+    name.trim().eq_ignore_ascii_case(TEMPLATE_HEADER)
+}
+
+/// Finds the MailerSend template requested by a campaign tag or header.
+/// Invalid template ids are ignored so that the raw body is sent instead.
+pub fn template_id(tags: &[String], headers: &[HashMap<String, String>]) -> Option<String> {
+    let from_tags = tags
+        .iter()
+        .filter_map(|x| x.strip_prefix(TEMPLATE_TAG_PREFIX));
+    let from_headers = headers
+        .iter()
+        .flat_map(|x| x.iter())
+        .filter(|(name, _)| is_template_header(name))
+        .map(|(_, value)| value.as_str());
+    for id in from_tags.chain(from_headers) {
+        let id = id.trim();
+        if TEMPLATE_ID_REGEX.is_match(id) {
+            return Some(id.to_string());
+        }
+        log::warn!("Ignoring invalid MailerSend template id {:?}", id);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_id_from_tag() {
+        let tags = vec![
+            "newsletter".to_string(),
+            "mailersend-template:0z76k5jg0o3yeg2d".to_string(),
+        ];
+        assert_eq!(
+            template_id(&tags, &[]),
+            Some("0z76k5jg0o3yeg2d".to_string())
+        );
+    }
+
+    #[test]
+    fn test_template_id_from_header() {
+        let headers = vec![HashMap::from([(
+            "x-mailersend-template".to_string(),
+            " 0z76k5jg0o3yeg2d ".to_string(),
+        )])];
+        assert_eq!(
+            template_id(&[], &headers),
+            Some("0z76k5jg0o3yeg2d".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_or_missing_template_id() {
+        let tags = vec!["mailersend-template:<script>".to_string()];
+        assert_eq!(template_id(&tags, &[]), None);
+        assert_eq!(template_id(&["newsletter".to_string()], &[]), None);
+    }
+}
@@ -0,0 +1,101 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+pub const CAMPAIGN_TAG_PREFIX: &str = "campaign:";
+/// Subscribers are now only sent in the `X-Listmonk-Subscriber` header, this
+/// tag is still read for emails sent by earlier versions.
+pub const SUBSCRIBER_TAG_PREFIX: &str = "subscriber:";
+pub const CAMPAIGN_HEADER: &str = "X-Listmonk-Campaign";
+pub const SUBSCRIBER_HEADER: &str = "X-Listmonk-Subscriber";
+
+lazy_static! {
+    static ref VIEW_PIXEL_REGEX: Regex = Regex::new(
+        r#"(?i)<img[^>]*\ssrc\s*=\s*["'][^"']*/campaign/[0-9a-f-]+/[0-9a-f-]+/px\.png["'][^>]*>"#
+    )
+    .unwrap();
+    static ref TRACKED_LINK_REGEX: Regex =
+        Regex::new(r"(?i)^https?://[^?#]*/link/[0-9a-f-]+/[0-9a-f-]+/[0-9a-f-]+/?(?:[?#].*)?$")
+            .unwrap();
+}
+
+pub fn campaign_tag(campaign_uuid: &str) -> String {
+    format!("{}{}", CAMPAIGN_TAG_PREFIX, campaign_uuid)
+}
+
+pub fn tag_value<'a>(tags: &'a [String], prefix: &str) -> Option<&'a str> {
+    tags.iter().find_map(|x| x.strip_prefix(prefix))
+}
+
+/// Whether opens and clicks tracked by MailerSend are forwarded to listmonk.
+#[derive(Clone, Copy, Debug)]
+pub struct TrackingPolicy {
+    forward: bool,
+}
+
+impl TrackingPolicy {
+    pub fn new(forward: bool) -> Self {
+        TrackingPolicy { forward }
+    }
+
+    pub fn is_forwarded(&self) -> bool {
+        self.forward
+    }
+
+    /// Removes the listmonk view pixel when MailerSend opens are forwarded,
+    /// so that a single open is not counted twice.
+    pub fn html(&self, html: Option<String>) -> Option<String> {
+        match html {
+            Some(html) if self.forward => Some(VIEW_PIXEL_REGEX.replace_all(&html, "").to_string()),
+            html => html,
+        }
+    }
+}
+
+/// Links rewritten by listmonk's `TrackLink`, whose clicks listmonk counts
+/// itself when redirecting.
+pub fn is_tracked_link(url: &str) -> bool {
+    TRACKED_LINK_REGEX.is_match(url.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_view_pixel_is_stripped_when_forwarding() {
+        let html = r#"<p>Hello</p><img src="https://listmonk.app/campaign/a4d516f2-1c2b/1db7ee2a-9f3e/px.png" alt="" />"#;
+        assert_eq!(
+            TrackingPolicy::new(true).html(Some(html.to_string())),
+            Some("<p>Hello</p>".to_string())
+        );
+        assert_eq!(
+            TrackingPolicy::new(false).html(Some(html.to_string())),
+            Some(html.to_string())
+        );
+    }
+
+    #[test]
+    fn test_other_images_are_kept() {
+        let html = r#"<img src="https://devdot.blog/logo.png" alt="" />"#;
+        assert_eq!(
+            TrackingPolicy::new(true).html(Some(html.to_string())),
+            Some(html.to_string())
+        );
+    }
+
+    #[test]
+    fn test_is_tracked_link() {
+        assert!(is_tracked_link(
+            "https://listmonk.app/link/5e3b1f7a-7a3b/a4d516f2-1c2b/1db7ee2a-9f3e"
+        ));
+        assert!(!is_tracked_link("https://devdot.blog/link/article"));
+    }
+
+    #[test]
+    fn test_tag_value() {
+        let tags = vec![campaign_tag("a4d516f2"), "subscriber:1db7ee2a".to_string()];
+        assert_eq!(tag_value(&tags, CAMPAIGN_TAG_PREFIX), Some("a4d516f2"));
+        assert_eq!(tag_value(&tags, SUBSCRIBER_TAG_PREFIX), Some("1db7ee2a"));
+        assert_eq!(tag_value(&[], CAMPAIGN_TAG_PREFIX), None);
+    }
+}
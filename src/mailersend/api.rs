@@ -1,17 +1,26 @@
-use super::throttler::Throttler;
+use super::bulk_status::{BulkEmailResponse, BulkEmailStatus, BulkEmailStatusResponse};
+use super::rate_limiter::{RateLimitHeaders, RateLimiter};
+use super::retry::{Outcome, RetryPolicy};
 use actix_rt;
 use actix_rt::task::JoinHandle;
 use futures::future::join_all;
 use lazy_static::lazy_static;
 use log;
 use regex::Regex;
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::{
-    sync::{Arc, Mutex},
-    time::Duration,
-};
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+use std::ops::Range;
+use std::time::Duration;
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Attachments are repeated for every recipient, so bulk requests are also
+/// split to keep the base64 attachments sent in one request under this size.
+pub const MAX_BULK_ATTACHMENTS_SIZE: usize = 50 * 1024 * 1024;
+
+/// MailerSend rejects emails with more tags than this.
+pub const MAX_TAGS: usize = 5;
 
 lazy_static! {
     static ref RAW_EMAIL_REGEX: Regex =
@@ -59,6 +68,10 @@ impl EmailAddress {
     pub fn email(&self) -> &str {
         &self.email
     }
+
+    pub fn domain(&self) -> &str {
+        self.email.rsplit_once('@').map_or("", |(_, domain)| domain)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -67,15 +80,115 @@ pub struct Email {
     pub to: Vec<EmailAddress>,
     pub reply_to: Option<EmailAddress>,
     pub subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub html: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template_id: Option<String>,
     pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<Header>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub personalization: Vec<Personalization>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub content: String,
+    pub filename: String,
+    pub disposition: String,
+    /// Content id referenced from the HTML as `cid:<id>`, required by
+    /// MailerSend for inline attachments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+
+impl Email {
+    /// Size of the base64 encoded attachments.
+    pub fn attachments_size(&self) -> usize {
+        self.attachments.iter().map(|x| x.content.len()).sum()
+    }
+}
+
+#[cfg(test)]
+impl Email {
+    /// An HTML email to `to`, the base of the emails used in tests.
+    pub fn for_test(to: &str) -> Self {
+        Email {
+            from: EmailAddress::from_parts(None, "from@email.com"),
+            to: vec![EmailAddress::from_parts(None, to)],
+            reply_to: None,
+            subject: "Test subject".to_string(),
+            text: None,
+            html: Some("<h1>Test</h1>".to_string()),
+            template_id: None,
+            tags: vec![],
+            headers: vec![],
+            attachments: vec![],
+            personalization: vec![],
+        }
+    }
+
+    pub fn with_tags(mut self, tags: &[&str]) -> Self {
+        self.tags = tags.iter().map(|x| x.to_string()).collect();
+        self
+    }
+}
+
+/// Ranges of the emails sent in each of the consecutive bulk requests. Each
+/// holds at most `bulk_size` emails and, unless it is a single email, at most
+/// `MAX_BULK_ATTACHMENTS_SIZE` of attachments.
+fn bulk_chunks(emails: &[Email], bulk_size: usize) -> Vec<Range<usize>> {
+    let mut result = Vec::new();
+    let (mut start, mut size) = (0, 0);
+    for (index, email) in emails.iter().enumerate() {
+        let email_size = email.attachments_size();
+        if index > start
+            && (index - start >= bulk_size || size + email_size > MAX_BULK_ATTACHMENTS_SIZE)
+        {
+            result.push(start..index);
+            (start, size) = (index, 0);
+        }
+        size += email_size;
+    }
+    if start < emails.len() {
+        result.push(start..emails.len());
+    }
+    result
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Personalization {
+    pub email: String,
+    pub data: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Header {
+    pub name: String,
+    pub value: String,
 }
 
 #[derive(Debug, Clone)]
-struct ChunkResult {
-    api_response_status: u16,
-    api_response_message: String,
+pub struct ChunkResult {
+    pub api_response_status: u16,
+    pub api_response_message: String,
+    pub api_response_body: String,
+}
+
+impl ChunkResult {
+    pub fn outcome(&self) -> Outcome {
+        Outcome::from_status(self.api_response_status)
+    }
+
+    pub fn bulk_email_id(&self) -> Option<String> {
+        serde_json::from_str::<BulkEmailResponse>(&self.api_response_body)
+            .ok()
+            .map(|x| x.bulk_email_id)
+    }
 }
 
 #[derive(Clone)]
@@ -83,50 +196,81 @@ pub struct MailerSendAPI {
     http_client: Client,
     api_endpoint: String,
     api_token: String,
-    throttler: Arc<Mutex<Throttler>>,
+    rate_limiter: RateLimiter,
+    retry_policy: RetryPolicy,
 }
 
 impl MailerSendAPI {
-    pub fn new(api_endpoint: &str, api_key: &str, req_per_min: u32) -> Self {
+    pub fn new(
+        api_endpoint: &str,
+        api_key: &str,
+        rate_limiter: RateLimiter,
+        retry_policy: RetryPolicy,
+    ) -> Self {
         MailerSendAPI {
-            http_client: Client::new(),
+            http_client: Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("Failed to build HTTP client"),
             api_endpoint: api_endpoint.to_string(),
             api_token: api_key.to_string(),
-            throttler: Arc::new(Mutex::new(Throttler::new(req_per_min))),
+            rate_limiter,
+            retry_policy,
         }
     }
 
-    pub async fn send_bulk(&self, emails: Vec<Email>, bulk_size: usize) -> Result<()> {
+    /// Sends emails split into the chunks given by [`bulk_chunks`]. Results
+    /// are returned in the same order as the chunks, with the range of the
+    /// emails each chunk holds.
+    pub async fn send_bulk(
+        &self,
+        emails: Vec<Email>,
+        bulk_size: usize,
+    ) -> Vec<(Range<usize>, Result<ChunkResult>)> {
         log::info!("Sending {} emails in bulk", emails.len());
-        let chunks: Vec<&[Email]> = emails.chunks(bulk_size as usize).collect();
-        log::info!("Split emails list into {} chunks", chunks.len());
-        let chunk_results = join_all(chunks.iter().map(|x| self.send_bulk_chunk(x.to_vec()))).await;
+        let ranges = bulk_chunks(&emails, bulk_size);
+        log::info!("Split emails list into {} chunks", ranges.len());
+        let chunk_results = join_all(
+            ranges
+                .iter()
+                .map(|range| self.send_bulk_chunk(emails[range.clone()].to_vec())),
+        )
+        .await;
         log::info!("All MailerSend API requests finished");
-        let mut errors = String::new();
-        let mut had_errors = false;
-        for result in chunk_results {
-            match result.unwrap() {
-                Ok(res) => {
-                    log::info!("MailerSend API response: {:?}", res);
-                    if res.api_response_status < 200 || res.api_response_status >= 300 {
-                        had_errors = true;
-                        errors.push_str(&format!(
-                            "MailerSend API response: {} {}\n",
-                            res.api_response_status, res.api_response_message
-                        ));
-                    }
-                }
-                Err(err) => {
-                    had_errors = true;
-                    errors.push_str(&format!("MailerSend API request failed: {}\n", err));
-                }
-            }
-        }
-        if had_errors {
-            Err(errors.into())
-        } else {
-            Ok(())
+        ranges
+            .into_iter()
+            .zip(chunk_results)
+            .map(|(range, result)| match result {
+                Ok(result) => (range, result),
+                Err(err) => (
+                    range,
+                    Err(format!("MailerSend API request task failed: {}", err).into()),
+                ),
+            })
+            .collect()
+    }
+
+    pub async fn get_bulk_status(&self, bulk_email_id: &str) -> Result<BulkEmailStatus> {
+        self.rate_limiter.acquire().await;
+        let res = self
+            .http_client
+            .get(format!(
+                "{}/bulk-email/{}",
+                self.api_endpoint, bulk_email_id
+            ))
+            .header("X-Requested-With", "XMLHttpRequest")
+            .bearer_auth(&self.api_token)
+            .send()
+            .await?;
+        let status = res.status();
+        let rate_limit = RateLimitHeaders::from_headers(res.headers());
+        self.rate_limiter
+            .observe(&rate_limit, status == StatusCode::TOO_MANY_REQUESTS);
+        if !status.is_success() {
+            let message = res.text().await?;
+            return Err(format!("MailerSend API response: {} {}", status, message).into());
         }
+        Ok(res.json::<BulkEmailStatusResponse>().await?.data)
     }
 
     fn send_bulk_chunk(&self, emails_vec: Vec<Email>) -> JoinHandle<Result<ChunkResult>> {
@@ -134,34 +278,64 @@ impl MailerSendAPI {
         let client = self.http_client.clone();
         let api_endpoint = format!("{}/bulk-email", self.api_endpoint);
         let api_token = self.api_token.clone();
-        let throttler = self.throttler.clone();
+        let rate_limiter = self.rate_limiter.clone();
+        let retry_policy = self.retry_policy.clone();
         actix_rt::spawn(async move {
-            log::info!("Throttling MailerSend API request");
-            throttler
-                .lock()
-                .unwrap()
-                .try_blocking(Duration::from_secs(120));
-            log::info!("Sending MailerSend API request");
-            let res = client
-                .post(&api_endpoint)
-                .json(&emails_vec)
-                .header("Content-Type", "application/json")
-                .header("X-Requested-With", "XMLHttpRequest")
-                .bearer_auth(api_token)
-                .send()
-                .await;
-            match res {
-                Ok(res) => {
-                    log::info!("MailerSend API response: {:?}", res);
-                    Ok(ChunkResult {
-                        api_response_message: res.status().to_string(),
-                        api_response_status: res.status().into(),
-                    })
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                log::info!("Throttling MailerSend API request");
+                rate_limiter.acquire().await;
+                log::info!("Sending MailerSend API request (attempt {})", attempt);
+                let res = client
+                    .post(&api_endpoint)
+                    .json(&emails_vec)
+                    .header("Content-Type", "application/json")
+                    .header("X-Requested-With", "XMLHttpRequest")
+                    .bearer_auth(&api_token)
+                    .send()
+                    .await;
+                let (outcome, throttled, retry_after, result) = match res {
+                    Ok(res) => {
+                        log::info!("MailerSend API response: {:?}", res);
+                        let status = res.status();
+                        let throttled = status == StatusCode::TOO_MANY_REQUESTS;
+                        let rate_limit = RateLimitHeaders::from_headers(res.headers());
+                        rate_limiter.observe(&rate_limit, throttled);
+                        let retry_after = rate_limit.retry_after;
+                        let result = ChunkResult {
+                            api_response_message: status.to_string(),
+                            api_response_status: status.into(),
+                            api_response_body: res.text().await.unwrap_or_default(),
+                        };
+                        (result.outcome(), throttled, retry_after, Ok(result))
+                    }
+                    Err(err) => {
+                        log::error!("MailerSend API request failed: {}", err);
+                        (Outcome::from_error(&err), false, None, Err(err.into()))
+                    }
+                };
+                if outcome != Outcome::Retryable || attempt >= retry_policy.max_attempts {
+                    return result;
                 }
-                Err(err) => {
-                    log::error!("MailerSend API request failed: {}", err);
-                    Err(err.into())
+                if throttled {
+                    // The rate limiter holds back every request, this one
+                    // included, until the Retry-After of the 429 has passed.
+                    log::warn!(
+                        "Retrying MailerSend API request once the rate limit resets, after attempt {} of {}",
+                        attempt,
+                        retry_policy.max_attempts
+                    );
+                    continue;
                 }
+                let delay = retry_policy.delay(attempt, retry_after);
+                log::warn!(
+                    "Retrying MailerSend API request in {:?} after attempt {} of {}",
+                    delay,
+                    attempt,
+                    retry_policy.max_attempts
+                );
+                actix_rt::time::sleep(delay).await;
             }
         })
     }
@@ -170,6 +344,171 @@ impl MailerSendAPI {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mailersend::mock::{self, MockResponse};
+    use crate::mailersend::rate_limiter::{test_clock::MockClock, Clock};
+    use std::sync::Arc;
+
+    fn email() -> Email {
+        Email::for_test("to@email.com")
+    }
+
+    fn mailersend_api(endpoint: &str, clock: Arc<MockClock>) -> MailerSendAPI {
+        MailerSendAPI::new(
+            endpoint,
+            "token",
+            RateLimiter::with_clock(60, 10, clock),
+            RetryPolicy::new(3, Duration::from_millis(1), Duration::from_millis(10)),
+        )
+    }
+
+    fn accepted() -> MockResponse {
+        MockResponse::new(
+            202,
+            serde_json::json!({"message": "The bulk email is being processed.", "bulk_email_id": "bulk-1"}),
+        )
+    }
+
+    #[actix_rt::test]
+    async fn test_send_bulk_retries_server_errors() {
+        let (endpoint, requests) = mock::mailersend(&[
+            (
+                "POST /bulk-email",
+                MockResponse::new(500, serde_json::json!({"message": "Server Error"})),
+            ),
+            ("POST /bulk-email", accepted()),
+        ]);
+        let api = mailersend_api(&endpoint, MockClock::new());
+        let results = api.send_bulk(vec![email()], 10).await;
+        let result = results[0].1.as_ref().unwrap();
+        assert_eq!(result.api_response_status, 202);
+        assert_eq!(result.bulk_email_id().as_deref(), Some("bulk-1"));
+        assert_eq!(requests.find("POST /bulk-email").len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_send_bulk_waits_for_retry_after_once() {
+        let (endpoint, requests) = mock::mailersend(&[
+            (
+                "POST /bulk-email",
+                MockResponse::new(429, serde_json::json!({"message": "Too Many Attempts."}))
+                    .with_header("Retry-After", "30"),
+            ),
+            ("POST /bulk-email", accepted()),
+        ]);
+        let clock = MockClock::new();
+        let start = clock.now();
+        let api = MailerSendAPI::new(
+            &endpoint,
+            "token",
+            RateLimiter::with_clock(60, 10, clock.clone()),
+            RetryPolicy::new(3, Duration::from_secs(1), Duration::from_secs(60)),
+        );
+        let results =
+            actix_rt::time::timeout(Duration::from_secs(10), api.send_bulk(vec![email()], 10))
+                .await
+                .expect("Retry-After should only be waited for by the rate limiter");
+        assert_eq!(results[0].1.as_ref().unwrap().api_response_status, 202);
+        assert_eq!(requests.find("POST /bulk-email").len(), 2);
+        assert_eq!(clock.now() - start, Duration::from_secs(30));
+    }
+
+    #[actix_rt::test]
+    async fn test_send_bulk_gives_up_after_last_attempt() {
+        let (endpoint, requests) = mock::mailersend(&[(
+            "POST /bulk-email",
+            MockResponse::new(503, serde_json::json!({"message": "Service Unavailable"})),
+        )]);
+        let api = mailersend_api(&endpoint, MockClock::new());
+        let results = api.send_bulk(vec![email()], 10).await;
+        let result = results[0].1.as_ref().unwrap();
+        assert_eq!(result.api_response_status, 503);
+        assert_eq!(result.outcome(), Outcome::Retryable);
+        assert_eq!(requests.find("POST /bulk-email").len(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn test_send_bulk_does_not_retry_rejected_token() {
+        let (endpoint, requests) = mock::mailersend(&[(
+            "POST /bulk-email",
+            MockResponse::new(401, serde_json::json!({"message": "Unauthenticated."})),
+        )]);
+        let api = mailersend_api(&endpoint, MockClock::new());
+        let results = api.send_bulk(vec![email()], 10).await;
+        assert_eq!(
+            results[0].1.as_ref().unwrap().outcome(),
+            Outcome::Unauthorized
+        );
+        assert_eq!(requests.find("POST /bulk-email").len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_send_bulk_returns_chunk_ranges() {
+        let (endpoint, requests) = mock::mailersend(&[("POST /bulk-email", accepted())]);
+        let api = mailersend_api(&endpoint, MockClock::new());
+        let results = api.send_bulk(vec![email(), email(), email()], 2).await;
+        let ranges: Vec<Range<usize>> = results.iter().map(|x| x.0.clone()).collect();
+        assert_eq!(ranges, vec![0..2, 2..3]);
+        assert!(results.iter().all(|x| x.1.is_ok()));
+        assert_eq!(requests.find("POST /bulk-email").len(), 2);
+    }
+
+    #[test]
+    fn test_bulk_chunks_are_limited_by_attachments_size() {
+        let mut large = email();
+        large.attachments = vec![Attachment {
+            content: "a".repeat(MAX_BULK_ATTACHMENTS_SIZE / 2),
+            filename: "a.bin".to_string(),
+            disposition: "attachment".to_string(),
+            id: None,
+        }];
+        let small = email();
+        assert_eq!(
+            bulk_chunks(&[small.clone(), small.clone(), small.clone()], 2),
+            vec![0..2, 2..3]
+        );
+        assert_eq!(
+            bulk_chunks(
+                &[
+                    large.clone(),
+                    large.clone(),
+                    small.clone(),
+                    large.clone(),
+                    large.clone()
+                ],
+                10
+            ),
+            vec![0..3, 3..5]
+        );
+        let mut huge = email();
+        huge.attachments = vec![large.attachments[0].clone(); 3];
+        assert_eq!(
+            bulk_chunks(&[small.clone(), huge, small], 10),
+            vec![0..1, 1..2, 2..3]
+        );
+        assert!(bulk_chunks(&[], 10).is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_get_bulk_status_is_rate_limited() {
+        let (endpoint, requests) = mock::mailersend(&[(
+            "GET /bulk-email/bulk-1",
+            MockResponse::new(
+                200,
+                serde_json::json!({"data": {"state": "completed", "messages_id": ["62fb66bef54a112e920b5493"]}}),
+            )
+            .with_header("X-RateLimit-Limit", "60")
+            .with_header("X-RateLimit-Remaining", "0"),
+        )]);
+        let clock = MockClock::new();
+        let start = clock.now();
+        let api = mailersend_api(&endpoint, clock.clone());
+        let status = api.get_bulk_status("bulk-1").await.unwrap();
+        assert!(status.is_completed());
+        assert_eq!(clock.now(), start);
+        api.get_bulk_status("bulk-1").await.unwrap();
+        assert_eq!(clock.now() - start, Duration::from_secs(1));
+        assert_eq!(requests.find("GET /bulk-email/bulk-1").len(), 2);
+    }
 
     #[test]
     fn test_email_address_from_string() {
@@ -199,6 +538,12 @@ mod tests {
         assert_eq!(email.email, "john_doe@mail.com".to_string());
     }
 
+    #[test]
+    fn test_email_address_domain() {
+        let email = EmailAddress::from_string("John Doe <john_doe@mail.com>").unwrap();
+        assert_eq!(email.domain(), "mail.com");
+    }
+
     #[test]
     fn test_invalid_email_address_from_string() {
         let error = EmailAddress::from_string("not-an-email").unwrap_err();
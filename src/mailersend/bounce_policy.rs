@@ -0,0 +1,329 @@
+use chrono::{DateTime, Duration, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use rusqlite::params;
+
+use crate::storage::{timestamp, Database};
+
+lazy_static! {
+    static ref ENHANCED_STATUS_REGEX: Regex = Regex::new(r"\b[245]\.\d{1,3}\.\d{1,3}\b").unwrap();
+    static ref REPLY_CODE_REGEX: Regex = Regex::new(r"^\s*([245]\d{2})\b").unwrap();
+    /// Bounce reasons overriding the bounce type reported by MailerSend.
+    static ref BOUNCE_REASONS: Vec<(Regex, BounceClass)> = vec![
+        (
+            Regex::new(r"(?i)mailbox (is )?full|over ?quota|quota exceeded|insufficient (system )?storage").unwrap(),
+            BounceClass::Soft,
+        ),
+        (
+            Regex::new(r"(?i)gr[ae]ylist|try (again )?later|temporar(il)?y").unwrap(),
+            BounceClass::Soft,
+        ),
+        (
+            Regex::new(r"(?i)nxdomain|domain (does not|doesn't) exist|host (or domain name )?not found|no mx\b").unwrap(),
+            BounceClass::Hard,
+        ),
+        (
+            Regex::new(r"(?i)user unknown|no such (user|mailbox)|mailbox (does not exist|unavailable)|invalid recipient").unwrap(),
+            BounceClass::Hard,
+        ),
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BounceClass {
+    Soft,
+    Hard,
+}
+
+impl BounceClass {
+    /// Classifies a bounce by its reason, falling back to the type reported
+    /// by MailerSend when the reason is not recognized.
+    pub fn classify(reported: BounceClass, reason: Option<&str>) -> Self {
+        reason
+            .and_then(|reason| {
+                BOUNCE_REASONS
+                    .iter()
+                    .find(|(regex, _)| regex.is_match(reason))
+                    .map(|(_, class)| *class)
+            })
+            .unwrap_or(reported)
+    }
+}
+
+/// Extracts the SMTP status from a bounce reason, preferring the enhanced
+/// status code (such as `5.1.1`) over the leading reply code (such as `550`).
+pub fn smtp_status(reason: &str) -> Option<String> {
+    ENHANCED_STATUS_REGEX
+        .find(reason)
+        .map(|x| x.as_str().to_string())
+        .or_else(|| REPLY_CODE_REGEX.captures(reason).map(|x| x[1].to_string()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BounceAction {
+    /// Keep counting the soft bounce locally.
+    Ignore,
+    RecordSoft,
+    RecordHard,
+    Blocklist,
+}
+
+/// Counts soft bounces per address over a sliding window, escalating the
+/// soft bounce reaching the hard threshold to a hard bounce, and those from
+/// the blocklist threshold on to a blocklist. A zero hard threshold forwards
+/// every soft bounce to listmonk as is.
+#[derive(Clone)]
+pub struct BouncePolicy {
+    database: Database,
+    window: Duration,
+    hard_threshold: u32,
+    blocklist_threshold: Option<u32>,
+}
+
+impl BouncePolicy {
+    pub fn new(
+        database: Database,
+        window: Duration,
+        hard_threshold: u32,
+        blocklist_threshold: Option<u32>,
+    ) -> rusqlite::Result<Self> {
+        let connection = database.connection();
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS soft_bounces (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                email TEXT NOT NULL,
+                event_id TEXT NOT NULL UNIQUE,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        connection.execute(
+            "CREATE INDEX IF NOT EXISTS soft_bounces_email ON soft_bounces (email, created_at)",
+            [],
+        )?;
+        drop(connection);
+        Ok(BouncePolicy {
+            database,
+            window,
+            hard_threshold,
+            blocklist_threshold,
+        })
+    }
+
+    /// Decides what to do with a bounce event that happened at `at`. Soft
+    /// bounces are counted once per event id, so retried and redelivered
+    /// events lead to the same action.
+    pub fn action(
+        &self,
+        email: &str,
+        class: BounceClass,
+        event_id: &str,
+        at: DateTime<Utc>,
+    ) -> rusqlite::Result<BounceAction> {
+        if class == BounceClass::Hard {
+            return Ok(BounceAction::RecordHard);
+        }
+        if self.hard_threshold == 0 && self.blocklist_threshold.is_none() {
+            return Ok(BounceAction::RecordSoft);
+        }
+        let count = self.count_soft_bounce(email, event_id, at)?;
+        log::info!(
+            "{} soft bounces of {} in the last {} hours",
+            count,
+            email,
+            self.window.num_hours()
+        );
+        if self.blocklist_threshold.is_some_and(|x| count >= x) {
+            return Ok(BounceAction::Blocklist);
+        }
+        if self.hard_threshold == 0 {
+            return Ok(BounceAction::RecordSoft);
+        }
+        if count == self.hard_threshold {
+            return Ok(BounceAction::RecordHard);
+        }
+        Ok(BounceAction::Ignore)
+    }
+
+    /// Records a soft bounce unless its event was already recorded and returns
+    /// the number of soft bounces of the address within the window up to this
+    /// one, which stays the same when the event is retried.
+    fn count_soft_bounce(
+        &self,
+        email: &str,
+        event_id: &str,
+        at: DateTime<Utc>,
+    ) -> rusqlite::Result<u32> {
+        let email = email.to_lowercase();
+        let since = timestamp(at - self.window);
+        let mut connection = self.database.connection();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "DELETE FROM soft_bounces WHERE email = ?1 AND created_at < ?2",
+            params![email, since],
+        )?;
+        transaction.execute(
+            "INSERT OR IGNORE INTO soft_bounces (email, event_id, created_at)
+             VALUES (?1, ?2, ?3)",
+            params![email, event_id, timestamp(at)],
+        )?;
+        let count = transaction.query_row(
+            "SELECT COUNT(*) FROM soft_bounces WHERE email = ?1 AND created_at >= ?2
+             AND id <= (SELECT id FROM soft_bounces WHERE event_id = ?3)",
+            params![email, since, event_id],
+            |row| row.get(0),
+        )?;
+        transaction.commit()?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(hard_threshold: u32, blocklist_threshold: Option<u32>) -> BouncePolicy {
+        BouncePolicy::new(
+            Database::open_in_memory().unwrap(),
+            Duration::hours(72),
+            hard_threshold,
+            blocklist_threshold,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_classify_bounce_reason() {
+        assert_eq!(
+            BounceClass::classify(BounceClass::Hard, Some("552 Mailbox full")),
+            BounceClass::Soft
+        );
+        assert_eq!(
+            BounceClass::classify(BounceClass::Hard, Some("451 Greylisted, try again later")),
+            BounceClass::Soft
+        );
+        assert_eq!(
+            BounceClass::classify(BounceClass::Soft, Some("DNS lookup failed: NXDOMAIN")),
+            BounceClass::Hard
+        );
+        assert_eq!(
+            BounceClass::classify(BounceClass::Soft, Some("451 4.4.3 DNS lookup timed out")),
+            BounceClass::Soft
+        );
+        assert_eq!(
+            BounceClass::classify(BounceClass::Soft, Some("554 Rejected by DNSBL")),
+            BounceClass::Soft
+        );
+        assert_eq!(
+            BounceClass::classify(BounceClass::Soft, Some("Unknown reason")),
+            BounceClass::Soft
+        );
+        assert_eq!(
+            BounceClass::classify(BounceClass::Hard, None),
+            BounceClass::Hard
+        );
+    }
+
+    #[test]
+    fn test_smtp_status() {
+        assert_eq!(
+            smtp_status("550 5.1.1 <a@example.com>: User unknown").as_deref(),
+            Some("5.1.1")
+        );
+        assert_eq!(smtp_status("421 Try again later").as_deref(), Some("421"));
+        assert_eq!(smtp_status("Unknown reason"), None);
+        assert_eq!(smtp_status("Mailbox full since 2024"), None);
+    }
+
+    #[test]
+    fn test_soft_bounces_escalate_once_after_threshold() {
+        let policy = policy(3, Some(5));
+        let now = Utc::now();
+        let actions: Vec<BounceAction> = (0..5)
+            .map(|i| {
+                policy
+                    .action(
+                        "Anon@example.com",
+                        BounceClass::Soft,
+                        &format!("event-{}", i),
+                        now + Duration::hours(i),
+                    )
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                BounceAction::Ignore,
+                BounceAction::Ignore,
+                BounceAction::RecordHard,
+                BounceAction::Ignore,
+                BounceAction::Blocklist,
+            ]
+        );
+        assert_eq!(
+            policy
+                .action(
+                    "anon@example.com",
+                    BounceClass::Soft,
+                    "event-2",
+                    now + Duration::hours(2)
+                )
+                .unwrap(),
+            BounceAction::RecordHard
+        );
+    }
+
+    #[test]
+    fn test_soft_bounces_outside_window_are_forgotten() {
+        let policy = policy(2, None);
+        let now = Utc::now();
+        let email = "anon@example.com";
+        assert_eq!(
+            policy
+                .action(email, BounceClass::Soft, "event-1", now)
+                .unwrap(),
+            BounceAction::Ignore
+        );
+        assert_eq!(
+            policy
+                .action(
+                    email,
+                    BounceClass::Soft,
+                    "event-2",
+                    now + Duration::hours(73)
+                )
+                .unwrap(),
+            BounceAction::Ignore
+        );
+        assert_eq!(
+            policy
+                .action(
+                    email,
+                    BounceClass::Soft,
+                    "event-3",
+                    now + Duration::hours(74)
+                )
+                .unwrap(),
+            BounceAction::RecordHard
+        );
+    }
+
+    #[test]
+    fn test_hard_bounces_and_disabled_policy() {
+        let policy = policy(0, None);
+        let now = Utc::now();
+        assert_eq!(
+            policy
+                .action("anon@example.com", BounceClass::Hard, "event-1", now)
+                .unwrap(),
+            BounceAction::RecordHard
+        );
+        assert_eq!(
+            policy
+                .action("anon@example.com", BounceClass::Soft, "event-2", now)
+                .unwrap(),
+            BounceAction::RecordSoft
+        );
+    }
+}
@@ -1,65 +1,146 @@
-use std::sync::Arc;
-
-use futures::lock::Mutex;
+use chrono::Utc;
+use rusqlite::params;
 
 use super::api::Email;
+use crate::storage::{from_json, to_json, Database};
+
+#[derive(Debug, Clone)]
+pub struct QueuedEmail {
+    pub id: i64,
+    pub email: Email,
+}
 
+/// Durable queue of outgoing emails. Popped emails stay in the database
+/// until they are acknowledged, and unacknowledged ones are replayed
+/// after a restart.
 #[derive(Clone)]
 pub struct Buffer {
-    emails: Arc<Mutex<Vec<Email>>>,
+    database: Database,
 }
 
 impl Buffer {
-    pub fn new() -> Self {
-        Buffer {
-            emails: Arc::new(Mutex::new(Vec::new())),
+    pub fn new(database: Database) -> rusqlite::Result<Self> {
+        let connection = database.connection();
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS outgoing_emails (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                payload TEXT NOT NULL,
+                in_flight INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        let replayed = connection.execute(
+            "UPDATE outgoing_emails SET in_flight = 0 WHERE in_flight = 1",
+            [],
+        )?;
+        if replayed > 0 {
+            log::info!("Replaying {} unacknowledged emails", replayed);
+        }
+        drop(connection);
+        Ok(Buffer { database })
+    }
+
+    pub async fn push_all(&self, emails_vec: Vec<Email>) -> rusqlite::Result<()> {
+        let mut connection = self.database.connection();
+        let transaction = connection.transaction()?;
+        {
+            let mut statement = transaction
+                .prepare("INSERT INTO outgoing_emails (payload, created_at) VALUES (?1, ?2)")?;
+            let created_at = Utc::now().to_rfc3339();
+            for email in emails_vec {
+                statement.execute(params![to_json(&email)?, created_at])?;
+            }
         }
+        transaction.commit()
     }
 
-    pub async fn push_all(&self, emails_vec: Vec<Email>) {
-        let mut emails = self.emails.lock().await;
-        emails.extend(emails_vec);
+    /// Returns all pending emails and marks them as in flight.
+    pub async fn pop_all(&self) -> rusqlite::Result<Vec<QueuedEmail>> {
+        let connection = self.database.connection();
+        let mut statement = connection.prepare(
+            "UPDATE outgoing_emails SET in_flight = 1 WHERE in_flight = 0 RETURNING id, payload",
+        )?;
+        let mut result = statement
+            .query_map([], |row| {
+                let payload: String = row.get(1)?;
+                Ok(QueuedEmail {
+                    id: row.get(0)?,
+                    email: from_json(1, &payload)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        result.sort_by_key(|x| x.id);
+        Ok(result)
     }
 
-    pub async fn pop_all(&self) -> Vec<Email> {
-        let mut emails = self.emails.lock().await;
-        let mut result = Vec::new();
-        std::mem::swap(&mut result, &mut emails);
-        result
+    /// Removes emails acknowledged by MailerSend.
+    pub async fn ack(&self, ids: &[i64]) -> rusqlite::Result<()> {
+        self.update_all("DELETE FROM outgoing_emails WHERE id = ?1", ids)
+    }
+
+    /// Returns in flight emails back to the queue.
+    pub async fn release(&self, ids: &[i64]) -> rusqlite::Result<()> {
+        self.update_all(
+            "UPDATE outgoing_emails SET in_flight = 0 WHERE id = ?1",
+            ids,
+        )
+    }
+
+    fn update_all(&self, sql: &str, ids: &[i64]) -> rusqlite::Result<()> {
+        let mut connection = self.database.connection();
+        let transaction = connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(sql)?;
+            for id in ids {
+                statement.execute([id])?;
+            }
+        }
+        transaction.commit()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::mailersend::api::EmailAddress;
-
     use super::*;
 
+    fn emails() -> Vec<Email> {
+        vec![
+            Email::for_test("recipient@email.com").with_tags(&["test"]),
+            Email::for_test("recipient2@email.com").with_tags(&["test"]),
+        ]
+    }
+
     #[actix_rt::test]
     async fn test_buffer() {
-        let buffer = Buffer::new();
-        let emails = vec![
-            Email {
-                from: EmailAddress::from_parts(None, "testemail@email.com"),
-                to: vec![EmailAddress::from_parts(None, "recipient@email.com")],
-                reply_to: None,
-                subject: "Test subject".to_string(),
-                text: None,
-                html: Some("<h1>Test</h1>".to_string()),
-                tags: vec!["test".to_string()],
-            },
-            Email {
-                from: EmailAddress::from_parts(None, "testemail@email.com"),
-                to: vec![EmailAddress::from_parts(None, "recipient2@email.com")],
-                reply_to: None,
-                subject: "Test subject".to_string(),
-                text: None,
-                html: Some("<h1>Test</h1>".to_string()),
-                tags: vec!["test".to_string()],
-            },
-        ];
-        buffer.push_all(emails.clone()).await;
-        let popped_emails = buffer.pop_all().await;
+        let buffer = Buffer::new(Database::open_in_memory().unwrap()).unwrap();
+        buffer.push_all(emails()).await.unwrap();
+        let popped_emails = buffer.pop_all().await.unwrap();
         assert_eq!(popped_emails.len(), 2);
+        assert_eq!(popped_emails[0].email.to, emails()[0].to);
+        assert!(buffer.pop_all().await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_buffer_ack_and_release() {
+        let buffer = Buffer::new(Database::open_in_memory().unwrap()).unwrap();
+        buffer.push_all(emails()).await.unwrap();
+        let popped_emails = buffer.pop_all().await.unwrap();
+        buffer.ack(&[popped_emails[0].id]).await.unwrap();
+        buffer.release(&[popped_emails[1].id]).await.unwrap();
+        let popped_again = buffer.pop_all().await.unwrap();
+        assert_eq!(popped_again.len(), 1);
+        assert_eq!(popped_again[0].id, popped_emails[1].id);
+    }
+
+    #[actix_rt::test]
+    async fn test_buffer_replays_unacknowledged_emails_on_startup() {
+        let database = Database::open_in_memory().unwrap();
+        let buffer = Buffer::new(database.clone()).unwrap();
+        buffer.push_all(emails()).await.unwrap();
+        assert_eq!(buffer.pop_all().await.unwrap().len(), 2);
+
+        let restarted_buffer = Buffer::new(database).unwrap();
+        assert_eq!(restarted_buffer.pop_all().await.unwrap().len(), 2);
     }
 }
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use regex::Regex;
+use rusqlite::params;
+use serde::Deserialize;
+
+use super::api::Email;
+use crate::storage::{from_json, to_json, Database};
+
+lazy_static! {
+    static ref VALIDATION_ERROR_KEY_REGEX: Regex =
+        Regex::new(r"^(?:message\.)?(?<index>\d+)\.(?<field>.+)$").unwrap();
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BulkEmailResponse {
+    pub bulk_email_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BulkEmailStatusResponse {
+    pub data: BulkEmailStatus,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BulkEmailStatus {
+    pub state: String,
+    pub total_recipients_count: Option<u64>,
+    pub suppressed_recipients_count: Option<u64>,
+    pub validation_errors_count: Option<u64>,
+    pub validation_errors: Option<serde_json::Value>,
+    pub messages_id: Option<Vec<String>>,
+}
+
+impl BulkEmailStatus {
+    pub fn is_completed(&self) -> bool {
+        self.state == "completed" || self.state == "failed"
+    }
+
+    /// Groups validation errors by the index of the email in the bulk request.
+    /// MailerSend reports them under keys such as `message.1.from.email`.
+    pub fn validation_errors_by_index(&self) -> BTreeMap<usize, serde_json::Value> {
+        let mut result = BTreeMap::new();
+        let errors = match self.validation_errors.as_ref().and_then(|x| x.as_object()) {
+            None => return result,
+            Some(errors) => errors,
+        };
+        for (key, messages) in errors {
+            let (index, field) = match VALIDATION_ERROR_KEY_REGEX.captures(key) {
+                None => continue,
+                Some(groups) => (
+                    groups["index"].parse::<usize>(),
+                    groups["field"].to_string(),
+                ),
+            };
+            if let Ok(index) = index {
+                result
+                    .entry(index)
+                    .or_insert_with(|| serde_json::json!({}))
+                    .as_object_mut()
+                    .unwrap()
+                    .insert(field, messages.clone());
+            }
+        }
+        result
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackedBulkEmail {
+    pub bulk_email_id: String,
+    pub emails: Vec<Email>,
+}
+
+/// Bulk requests accepted by MailerSend whose processing is not yet completed.
+/// Claimed bulk requests are not returned to other status polls until they
+/// are released, and claims are dropped after a restart.
+#[derive(Clone)]
+pub struct BulkEmailStore {
+    database: Database,
+}
+
+impl BulkEmailStore {
+    pub fn new(database: Database) -> rusqlite::Result<Self> {
+        let connection = database.connection();
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS bulk_emails (
+                bulk_email_id TEXT PRIMARY KEY,
+                payload TEXT NOT NULL,
+                in_flight INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        connection.execute(
+            "UPDATE bulk_emails SET in_flight = 0 WHERE in_flight = 1",
+            [],
+        )?;
+        drop(connection);
+        Ok(BulkEmailStore { database })
+    }
+
+    pub fn track(&self, bulk_email_id: &str, emails: &[Email]) -> rusqlite::Result<()> {
+        self.database.connection().execute(
+            "INSERT OR REPLACE INTO bulk_emails (bulk_email_id, payload, created_at)
+             VALUES (?1, ?2, ?3)",
+            params![bulk_email_id, to_json(&emails)?, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the bulk requests not claimed by another poll, oldest first,
+    /// and marks them as claimed.
+    pub fn claim_pending(&self) -> rusqlite::Result<Vec<TrackedBulkEmail>> {
+        let connection = self.database.connection();
+        let mut statement = connection.prepare(
+            "UPDATE bulk_emails SET in_flight = 1 WHERE in_flight = 0
+             RETURNING bulk_email_id, payload, created_at",
+        )?;
+        let mut result = statement
+            .query_map([], |row| {
+                let payload: String = row.get(1)?;
+                let created_at: String = row.get(2)?;
+                Ok((
+                    created_at,
+                    TrackedBulkEmail {
+                        bulk_email_id: row.get(0)?,
+                        emails: from_json(1, &payload)?,
+                    },
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        result.sort_by(|x, y| x.0.cmp(&y.0));
+        Ok(result.into_iter().map(|(_, x)| x).collect())
+    }
+
+    /// Returns a claimed bulk request to be polled again later.
+    pub fn release(&self, bulk_email_id: &str) -> rusqlite::Result<()> {
+        self.database.connection().execute(
+            "UPDATE bulk_emails SET in_flight = 0 WHERE bulk_email_id = ?1",
+            [bulk_email_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn complete(&self, bulk_email_id: &str) -> rusqlite::Result<()> {
+        self.database.connection().execute(
+            "DELETE FROM bulk_emails WHERE bulk_email_id = ?1",
+            [bulk_email_id],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bulk_email_status() {
+        let status: BulkEmailStatusResponse = serde_json::from_str(
+            r#"{
+                "data": {
+                    "id": "614470d1588b866d0454f3e2",
+                    "state": "completed",
+                    "total_recipients_count": 3,
+                    "suppressed_recipients_count": 0,
+                    "suppressed_recipients": null,
+                    "validation_errors_count": 1,
+                    "validation_errors": {
+                        "message.1.from.email": ["The from.email must be verified."],
+                        "message.1.subject": ["The subject is required."],
+                        "message.2.to.0.email": ["The to.0.email must be a valid email address."],
+                        "unrelated": ["Ignored"]
+                    },
+                    "messages_id": ["61487a14608b1d0b4d506633"],
+                    "created_at": "2021-09-17T11:04:17.000000Z",
+                    "updated_at": "2021-09-17T11:04:17.000000Z"
+                }
+            }"#,
+        )
+        .unwrap();
+        let status = status.data;
+        assert!(status.is_completed());
+        let errors = status.validation_errors_by_index();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[&1]["from.email"][0],
+            "The from.email must be verified."
+        );
+        assert_eq!(errors[&1]["subject"][0], "The subject is required.");
+        assert!(errors[&2]["to.0.email"].is_array());
+    }
+
+    #[test]
+    fn test_bulk_email_store() {
+        let store = BulkEmailStore::new(Database::open_in_memory().unwrap()).unwrap();
+        let emails = vec![Email::for_test("to@email.com")];
+        store.track("bulk-1", &emails).unwrap();
+        let pending = store.claim_pending().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].bulk_email_id, "bulk-1");
+        assert_eq!(pending[0].emails[0].to, emails[0].to);
+        assert!(store.claim_pending().unwrap().is_empty());
+        store.release("bulk-1").unwrap();
+        assert_eq!(store.claim_pending().unwrap().len(), 1);
+        store.complete("bulk-1").unwrap();
+        store.release("bulk-1").unwrap();
+        assert!(store.claim_pending().unwrap().is_empty());
+    }
+}
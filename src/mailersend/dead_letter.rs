@@ -0,0 +1,141 @@
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension, Row};
+use serde::Serialize;
+
+use super::api::Email;
+use crate::storage::{from_json, to_json, Database};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeadLetter {
+    pub id: i64,
+    pub email: Email,
+    pub status: u16,
+    pub error: serde_json::Value,
+    pub created_at: String,
+}
+
+impl DeadLetter {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let payload: String = row.get(1)?;
+        let error: String = row.get(3)?;
+        Ok(DeadLetter {
+            id: row.get(0)?,
+            email: from_json(1, &payload)?,
+            status: row.get(2)?,
+            error: serde_json::from_str(&error).unwrap_or(serde_json::Value::String(error)),
+            created_at: row.get(4)?,
+        })
+    }
+}
+
+/// Emails permanently rejected by MailerSend, kept for inspection and replay.
+#[derive(Clone)]
+pub struct DeadLetterStore {
+    database: Database,
+}
+
+impl DeadLetterStore {
+    pub fn new(database: Database) -> rusqlite::Result<Self> {
+        database.connection().execute(
+            "CREATE TABLE IF NOT EXISTS dead_letters (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                payload TEXT NOT NULL,
+                status INTEGER NOT NULL,
+                error TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        Ok(DeadLetterStore { database })
+    }
+
+    pub fn add_all(&self, emails: Vec<Email>, status: u16, error: &str) -> rusqlite::Result<()> {
+        let mut connection = self.database.connection();
+        let transaction = connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT INTO dead_letters (payload, status, error, created_at) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            let created_at = Utc::now().to_rfc3339();
+            for email in emails {
+                statement.execute(params![to_json(&email)?, status, error, created_at])?;
+            }
+        }
+        transaction.commit()
+    }
+
+    pub fn list(&self, limit: u32, offset: u32) -> rusqlite::Result<Vec<DeadLetter>> {
+        let connection = self.database.connection();
+        let mut statement = connection.prepare(
+            "SELECT id, payload, status, error, created_at FROM dead_letters
+             ORDER BY id LIMIT ?1 OFFSET ?2",
+        )?;
+        let result = statement
+            .query_map([limit, offset], DeadLetter::from_row)?
+            .collect();
+        result
+    }
+
+    pub fn get(&self, id: i64) -> rusqlite::Result<Option<DeadLetter>> {
+        self.database
+            .connection()
+            .query_row(
+                "SELECT id, payload, status, error, created_at FROM dead_letters WHERE id = ?1",
+                [id],
+                DeadLetter::from_row,
+            )
+            .optional()
+    }
+
+    /// Returns whether the dead letter existed.
+    pub fn delete(&self, id: i64) -> rusqlite::Result<bool> {
+        let deleted = self
+            .database
+            .connection()
+            .execute("DELETE FROM dead_letters WHERE id = ?1", [id])?;
+        Ok(deleted > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email(to: &str) -> Email {
+        Email::for_test(to)
+    }
+
+    #[test]
+    fn test_dead_letter_store() {
+        let store = DeadLetterStore::new(Database::open_in_memory().unwrap()).unwrap();
+        let error = r#"{"message":"The from.email domain must be verified."}"#;
+        store
+            .add_all(vec![email("a@email.com"), email("b@email.com")], 422, error)
+            .unwrap();
+
+        let dead_letters = store.list(10, 0).unwrap();
+        assert_eq!(dead_letters.len(), 2);
+        assert_eq!(dead_letters[0].status, 422);
+        assert_eq!(
+            dead_letters[0].error["message"],
+            "The from.email domain must be verified."
+        );
+
+        let dead_letter = store.get(dead_letters[1].id).unwrap().unwrap();
+        assert_eq!(dead_letter.email.to, email("b@email.com").to);
+
+        assert!(store.delete(dead_letter.id).unwrap());
+        assert!(!store.delete(dead_letter.id).unwrap());
+        assert!(store.get(dead_letter.id).unwrap().is_none());
+        assert_eq!(store.list(10, 0).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_dead_letter_with_plain_text_error() {
+        let store = DeadLetterStore::new(Database::open_in_memory().unwrap()).unwrap();
+        store
+            .add_all(vec![email("a@email.com")], 422, "Unprocessable")
+            .unwrap();
+        assert_eq!(store.list(10, 0).unwrap()[0].error, "Unprocessable");
+    }
+}
@@ -0,0 +1,332 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Row};
+use serde::Serialize;
+
+use super::api::Email;
+use crate::listmonk::tracking::{self, CAMPAIGN_TAG_PREFIX};
+use crate::storage::{timestamp, to_json, Database};
+
+/// Email accepted by MailerSend as part of a bulk request.
+pub const SEND_ACCEPTED: &str = "send.accepted";
+/// Email permanently rejected by MailerSend.
+pub const SEND_REJECTED: &str = "send.rejected";
+/// Send attempt that failed and will be retried.
+pub const SEND_FAILED: &str = "send.failed";
+/// Email that failed the validation of an accepted bulk request.
+pub const SEND_INVALID: &str = "send.invalid";
+
+/// A send attempt or a MailerSend webhook event concerning one recipient.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DeliveryEvent {
+    pub event: String,
+    pub recipient: String,
+    pub campaign_uuid: Option<String>,
+    pub message_id: Option<String>,
+    /// Id of the MailerSend webhook event, used to log it once.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<String>,
+    pub details: serde_json::Value,
+    pub created_at: String,
+}
+
+impl DeliveryEvent {
+    pub fn new(event: &str, recipient: &str, created_at: DateTime<Utc>) -> Self {
+        DeliveryEvent {
+            event: event.to_string(),
+            recipient: recipient.to_lowercase(),
+            campaign_uuid: None,
+            message_id: None,
+            event_id: None,
+            details: serde_json::Value::Null,
+            created_at: timestamp(created_at),
+        }
+    }
+
+    pub fn with_campaign_uuid(mut self, campaign_uuid: Option<String>) -> Self {
+        self.campaign_uuid = campaign_uuid;
+        self
+    }
+
+    pub fn with_message_id(mut self, message_id: Option<String>) -> Self {
+        self.message_id = message_id;
+        self
+    }
+
+    pub fn with_event_id(mut self, event_id: &str) -> Self {
+        self.event_id = Some(event_id.to_string());
+        self
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct LoggedDeliveryEvent {
+    pub id: i64,
+    #[serde(flatten)]
+    pub event: DeliveryEvent,
+}
+
+impl LoggedDeliveryEvent {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let details: String = row.get(6)?;
+        Ok(LoggedDeliveryEvent {
+            id: row.get(0)?,
+            event: DeliveryEvent {
+                event: row.get(1)?,
+                recipient: row.get(2)?,
+                campaign_uuid: row.get(3)?,
+                message_id: row.get(4)?,
+                event_id: row.get(5)?,
+                details: serde_json::from_str(&details)
+                    .unwrap_or(serde_json::Value::String(details)),
+                created_at: row.get(7)?,
+            },
+        })
+    }
+}
+
+const COLUMNS: &str =
+    "id, event, recipient, campaign_uuid, message_id, event_id, details, created_at";
+
+/// Local record of what happened to every email after it was queued: send
+/// attempts and MailerSend webhook events, queryable per campaign and per
+/// recipient.
+#[derive(Clone)]
+pub struct DeliveryLog {
+    database: Database,
+}
+
+impl DeliveryLog {
+    pub fn new(database: Database) -> rusqlite::Result<Self> {
+        let connection = database.connection();
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS delivery_events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                event TEXT NOT NULL,
+                recipient TEXT NOT NULL,
+                campaign_uuid TEXT,
+                message_id TEXT,
+                event_id TEXT UNIQUE,
+                details TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        for index in [
+            "CREATE INDEX IF NOT EXISTS delivery_events_campaign ON delivery_events (campaign_uuid, created_at)",
+            "CREATE INDEX IF NOT EXISTS delivery_events_recipient ON delivery_events (recipient, created_at)",
+            "CREATE INDEX IF NOT EXISTS delivery_events_created_at ON delivery_events (created_at)",
+        ] {
+            connection.execute(index, [])?;
+        }
+        drop(connection);
+        Ok(DeliveryLog { database })
+    }
+
+    /// Logs events, ignoring webhook events that were already logged.
+    pub fn add_all(&self, events: &[DeliveryEvent]) -> rusqlite::Result<()> {
+        let mut connection = self.database.connection();
+        let transaction = connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT OR IGNORE INTO delivery_events
+                 (event, recipient, campaign_uuid, message_id, event_id, details, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for event in events {
+                statement.execute(params![
+                    event.event,
+                    event.recipient,
+                    event.campaign_uuid,
+                    event.message_id,
+                    event.event_id,
+                    to_json(&event.details)?,
+                    event.created_at,
+                ])?;
+            }
+        }
+        transaction.commit()
+    }
+
+    /// Logs a send attempt for every recipient of the emails.
+    pub fn add_sends(
+        &self,
+        emails: &[Email],
+        event: &str,
+        details: serde_json::Value,
+    ) -> rusqlite::Result<()> {
+        let now = Utc::now();
+        let events: Vec<DeliveryEvent> = emails
+            .iter()
+            .flat_map(|email| {
+                let campaign_uuid =
+                    tracking::tag_value(&email.tags, CAMPAIGN_TAG_PREFIX).map(|x| x.to_string());
+                let details = details.clone();
+                email.to.iter().map(move |recipient| {
+                    DeliveryEvent::new(event, recipient.email(), now)
+                        .with_campaign_uuid(campaign_uuid.clone())
+                        .with_details(details.clone())
+                })
+            })
+            .collect();
+        self.add_all(&events)
+    }
+
+    /// Sets the MailerSend message id on the accepted sends of a bulk
+    /// request, which are logged before MailerSend assigns the ids.
+    pub fn set_message_ids(
+        &self,
+        bulk_email_id: &str,
+        messages: &[(&Email, &str)],
+    ) -> rusqlite::Result<()> {
+        let mut connection = self.database.connection();
+        let transaction = connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "UPDATE delivery_events SET message_id = ?1 WHERE id = (
+                     SELECT id FROM delivery_events
+                     WHERE event = ?2 AND recipient = ?3 AND message_id IS NULL
+                     AND json_extract(details, '$.bulk_email_id') = ?4
+                     ORDER BY id LIMIT 1
+                 )",
+            )?;
+            for (email, message_id) in messages {
+                for recipient in &email.to {
+                    statement.execute(params![
+                        message_id,
+                        SEND_ACCEPTED,
+                        recipient.email().to_lowercase(),
+                        bulk_email_id,
+                    ])?;
+                }
+            }
+        }
+        transaction.commit()
+    }
+
+    pub fn campaign_timeline(
+        &self,
+        campaign_uuid: &str,
+        limit: u32,
+        offset: u32,
+    ) -> rusqlite::Result<Vec<LoggedDeliveryEvent>> {
+        self.timeline("campaign_uuid", campaign_uuid, limit, offset)
+    }
+
+    pub fn recipient_timeline(
+        &self,
+        recipient: &str,
+        limit: u32,
+        offset: u32,
+    ) -> rusqlite::Result<Vec<LoggedDeliveryEvent>> {
+        self.timeline("recipient", &recipient.to_lowercase(), limit, offset)
+    }
+
+    fn timeline(
+        &self,
+        column: &str,
+        value: &str,
+        limit: u32,
+        offset: u32,
+    ) -> rusqlite::Result<Vec<LoggedDeliveryEvent>> {
+        let connection = self.database.connection();
+        let mut statement = connection.prepare(&format!(
+            "SELECT {} FROM delivery_events WHERE {} = ?1
+             ORDER BY created_at, id LIMIT ?2 OFFSET ?3",
+            COLUMNS, column
+        ))?;
+        let result = statement
+            .query_map(params![value, limit, offset], LoggedDeliveryEvent::from_row)?
+            .collect();
+        result
+    }
+
+    /// Removes the events older than `before`, returning how many were removed.
+    pub fn prune(&self, before: DateTime<Utc>) -> rusqlite::Result<usize> {
+        self.database.connection().execute(
+            "DELETE FROM delivery_events WHERE created_at < ?1",
+            [timestamp(before)],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn email(to: &str, tags: &[&str]) -> Email {
+        Email::for_test(to).with_tags(tags)
+    }
+
+    fn delivery_log() -> DeliveryLog {
+        DeliveryLog::new(Database::open_in_memory().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_timelines() {
+        let log = delivery_log();
+        log.add_sends(
+            &[
+                email("A@email.com", &["campaign:a4d516f2"]),
+                email("b@email.com", &["campaign:a4d516f2"]),
+                email("a@email.com", &[]),
+            ],
+            SEND_ACCEPTED,
+            serde_json::json!({"bulk_email_id": "bulk-1"}),
+        )
+        .unwrap();
+        let delivered = DeliveryEvent::new("activity.delivered", "a@email.com", Utc::now())
+            .with_campaign_uuid(Some("a4d516f2".to_string()))
+            .with_message_id(Some("62fb66bef54a112e920b5493".to_string()))
+            .with_event_id("62f114f8165fe0d8db0288e5");
+        log.add_all(std::slice::from_ref(&delivered)).unwrap();
+        log.add_all(&[delivered]).unwrap();
+
+        let campaign = log.campaign_timeline("a4d516f2", 100, 0).unwrap();
+        let events: Vec<(&str, &str)> = campaign
+            .iter()
+            .map(|x| (x.event.event.as_str(), x.event.recipient.as_str()))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (SEND_ACCEPTED, "a@email.com"),
+                (SEND_ACCEPTED, "b@email.com"),
+                ("activity.delivered", "a@email.com"),
+            ]
+        );
+        assert_eq!(campaign[0].event.details["bulk_email_id"], "bulk-1");
+
+        let recipient = log.recipient_timeline("A@Email.com", 100, 0).unwrap();
+        assert_eq!(recipient.len(), 3);
+        assert_eq!(recipient[1].event.campaign_uuid, None);
+        assert_eq!(
+            recipient[2].event.message_id.as_deref(),
+            Some("62fb66bef54a112e920b5493")
+        );
+        assert_eq!(
+            log.recipient_timeline("a@email.com", 1, 2).unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_prune() {
+        let log = delivery_log();
+        let now = Utc::now();
+        log.add_all(&[
+            DeliveryEvent::new(SEND_ACCEPTED, "a@email.com", now - Duration::days(31)),
+            DeliveryEvent::new("activity.delivered", "a@email.com", now),
+        ])
+        .unwrap();
+        assert_eq!(log.prune(now - Duration::days(30)).unwrap(), 1);
+        let timeline = log.recipient_timeline("a@email.com", 100, 0).unwrap();
+        assert_eq!(timeline.len(), 1);
+        assert_eq!(timeline[0].event.event, "activity.delivered");
+    }
+}
@@ -0,0 +1,330 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// MailerSend webhook event types. Events added by MailerSend after this
+/// list was written are kept as `Unknown` instead of being rejected.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "String", into = "String")]
+pub enum WebhookEvent {
+    Sent,
+    Delivered,
+    SoftBounced,
+    HardBounced,
+    Opened,
+    OpenedUnique,
+    Clicked,
+    ClickedUnique,
+    Unsubscribed,
+    SpamComplaint,
+    SurveyOpened,
+    SurveySubmitted,
+    IdentityVerified,
+    MaintenanceStarted,
+    MaintenanceEnded,
+    Unknown(String),
+}
+
+const EVENTS: &[(&str, WebhookEvent)] = &[
+    ("activity.sent", WebhookEvent::Sent),
+    ("activity.delivered", WebhookEvent::Delivered),
+    ("activity.soft_bounced", WebhookEvent::SoftBounced),
+    ("activity.hard_bounced", WebhookEvent::HardBounced),
+    ("activity.opened", WebhookEvent::Opened),
+    ("activity.opened_unique", WebhookEvent::OpenedUnique),
+    ("activity.clicked", WebhookEvent::Clicked),
+    ("activity.clicked_unique", WebhookEvent::ClickedUnique),
+    ("activity.unsubscribed", WebhookEvent::Unsubscribed),
+    ("activity.spam_complaint", WebhookEvent::SpamComplaint),
+    ("activity.survey_opened", WebhookEvent::SurveyOpened),
+    ("activity.survey_submitted", WebhookEvent::SurveySubmitted),
+    ("sender_identity.verified", WebhookEvent::IdentityVerified),
+    ("maintenance.start", WebhookEvent::MaintenanceStarted),
+    ("maintenance.end", WebhookEvent::MaintenanceEnded),
+];
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &str {
+        match self {
+            WebhookEvent::Unknown(name) => name,
+            event => EVENTS
+                .iter()
+                .find(|(_, x)| x == event)
+                .map_or("", |(name, _)| name),
+        }
+    }
+
+    /// Whether the event reports an activity of a sent email, whose data
+    /// has the shape of [`ActivityData`].
+    pub fn is_activity(&self) -> bool {
+        !matches!(
+            self,
+            WebhookEvent::IdentityVerified
+                | WebhookEvent::MaintenanceStarted
+                | WebhookEvent::MaintenanceEnded
+                | WebhookEvent::Unknown(_)
+        )
+    }
+}
+
+impl From<String> for WebhookEvent {
+    fn from(name: String) -> Self {
+        EVENTS
+            .iter()
+            .find(|(x, _)| *x == name)
+            .map_or(WebhookEvent::Unknown(name), |(_, event)| event.clone())
+    }
+}
+
+impl From<WebhookEvent> for String {
+    fn from(event: WebhookEvent) -> Self {
+        event.as_str().to_string()
+    }
+}
+
+impl fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RecipientData {
+    pub object: String,
+    pub id: String,
+    pub email: String,
+    pub created_at: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct MessageData {
+    pub object: String,
+    pub id: String,
+    pub created_at: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct EmailData {
+    pub object: String,
+    pub id: String,
+    pub created_at: String,
+    pub from: String,
+    pub subject: String,
+    pub status: String,
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub headers: Option<serde_json::Value>,
+    #[serde(default)]
+    pub message: Option<MessageData>,
+    pub recipient: RecipientData,
+}
+
+impl EmailData {
+    /// Finds a custom header given either as a list of `{name, value}`
+    /// objects or as a map of names to values.
+    pub fn header(&self, name: &str) -> Option<String> {
+        match self.headers.as_ref()? {
+            serde_json::Value::Array(headers) => headers
+                .iter()
+                .find(|x| {
+                    x["name"]
+                        .as_str()
+                        .is_some_and(|x| x.eq_ignore_ascii_case(name))
+                })
+                .and_then(|x| x["value"].as_str()),
+            serde_json::Value::Object(headers) => headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .and_then(|(_, value)| value.as_str()),
+            _ => None,
+        }
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+    }
+}
+
+/// Details specific to the activity, such as the bounce reason or the
+/// clicked link. Which fields are set depends on the `object` type.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct MorphData {
+    pub object: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readable_reason: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ActivityData {
+    pub object: String,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub activity_type: String,
+    pub created_at: String,
+    pub email: EmailData,
+    #[serde(default)]
+    pub morph: Option<MorphData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template_id: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum WebhookData {
+    Activity(Box<ActivityData>),
+    /// Data of events other than activities, kept as sent by MailerSend.
+    Other(serde_json::Value),
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(try_from = "RawWebhookRequest")]
+pub struct WebhookRequest {
+    #[serde(rename = "type")]
+    pub event: WebhookEvent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain_id: Option<String>,
+    pub created_at: String,
+    pub webhook_id: String,
+    pub url: String,
+    pub data: WebhookData,
+}
+
+impl WebhookRequest {
+    /// Id of the event, the same across redeliveries of the webhook.
+    pub fn event_id(&self) -> Option<&str> {
+        match &self.data {
+            WebhookData::Activity(activity) => Some(&activity.id),
+            WebhookData::Other(data) => data["id"].as_str(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RawWebhookRequest {
+    #[serde(rename = "type")]
+    event: WebhookEvent,
+    #[serde(default)]
+    domain_id: Option<String>,
+    created_at: String,
+    webhook_id: String,
+    url: String,
+    data: serde_json::Value,
+}
+
+impl TryFrom<RawWebhookRequest> for WebhookRequest {
+    type Error = serde_json::Error;
+
+    fn try_from(raw: RawWebhookRequest) -> Result<Self, Self::Error> {
+        let data = if raw.event.is_activity() {
+            WebhookData::Activity(Box::new(serde_json::from_value(raw.data)?))
+        } else {
+            WebhookData::Other(raw.data)
+        };
+        Ok(WebhookRequest {
+            event: raw.event,
+            domain_id: raw.domain_id,
+            created_at: raw.created_at,
+            webhook_id: raw.webhook_id,
+            url: raw.url,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = include_str!("../../test/req_bounce.json");
+
+    #[test]
+    fn test_parse_activity_webhook() {
+        let payload: WebhookRequest = serde_json::from_str(BODY).unwrap();
+        assert_eq!(payload.event, WebhookEvent::SoftBounced);
+        assert_eq!(payload.event_id(), Some("62f114f8165fe0d8db0288e5"));
+        let WebhookData::Activity(activity) = &payload.data else {
+            panic!("Expected activity data");
+        };
+        assert_eq!(activity.template_id.as_deref(), Some("0z76k5jg0o3yeg2d"));
+        assert_eq!(
+            activity.email.message.as_ref().unwrap().id,
+            "62fb66bef54a112e920b5493"
+        );
+        let morph = activity.morph.as_ref().unwrap();
+        assert_eq!(morph.object, "recipient_bounce");
+        assert_eq!(morph.reason.as_deref(), Some("Unknown reason"));
+    }
+
+    #[test]
+    fn test_webhook_round_trip() {
+        let payload: WebhookRequest = serde_json::from_str(BODY).unwrap();
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["type"], "activity.soft_bounced");
+        assert_eq!(json["data"]["type"], "soft_bounced");
+        let parsed: WebhookRequest = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.event, WebhookEvent::SoftBounced);
+    }
+
+    #[test]
+    fn test_event_names() {
+        for (name, event) in EVENTS {
+            assert_eq!(WebhookEvent::from(name.to_string()), *event);
+            assert_eq!(event.as_str(), *name);
+        }
+        let event = WebhookEvent::from("activity.forwarded".to_string());
+        assert_eq!(
+            event,
+            WebhookEvent::Unknown("activity.forwarded".to_string())
+        );
+        assert_eq!(event.to_string(), "activity.forwarded");
+    }
+
+    #[test]
+    fn test_parse_unknown_webhook() {
+        let payload: WebhookRequest = serde_json::from_str(
+            r#"{
+                "type": "activity.forwarded",
+                "domain_id": "neqvygmm93wg0p7w",
+                "created_at": "2024-01-08T14:46:07.442816Z",
+                "webhook_id": "jpzkmgqdyml059v7",
+                "url": "https://example.com/webhooks",
+                "data": {"id": "62f114f8165fe0d8db0288e9", "forwarded_to": "someone"}
+            }"#,
+        )
+        .unwrap();
+        assert!(!payload.event.is_activity());
+        assert_eq!(payload.event_id(), Some("62f114f8165fe0d8db0288e9"));
+        assert!(matches!(payload.data, WebhookData::Other(_)));
+    }
+
+    #[test]
+    fn test_parse_sender_identity_webhook() {
+        let payload: WebhookRequest = serde_json::from_str(
+            r#"{
+                "type": "sender_identity.verified",
+                "created_at": "2024-01-08T14:46:07.442816Z",
+                "webhook_id": "jpzkmgqdyml059v7",
+                "url": "https://example.com/webhooks",
+                "data": {"object": "sender_identity", "email": "sender@example.com"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(payload.event, WebhookEvent::IdentityVerified);
+        assert_eq!(payload.domain_id, None);
+        assert_eq!(payload.event_id(), None);
+    }
+
+    #[test]
+    fn test_malformed_activity_is_rejected() {
+        let body = BODY.replace("\"recipient\": {", "\"recipient_\": {");
+        assert!(serde_json::from_str::<WebhookRequest>(&body).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{params, types::Type, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+use super::event::WebhookRequest;
+use crate::storage::{from_json, timestamp, to_json, Database};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InboxStatus {
+    Pending,
+    Processing,
+    Failed,
+}
+
+impl InboxStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            InboxStatus::Pending => "pending",
+            InboxStatus::Processing => "processing",
+            InboxStatus::Failed => "failed",
+        }
+    }
+
+    fn from_column(column: usize, value: &str) -> rusqlite::Result<Self> {
+        match value {
+            "pending" => Ok(InboxStatus::Pending),
+            "processing" => Ok(InboxStatus::Processing),
+            "failed" => Ok(InboxStatus::Failed),
+            _ => Err(rusqlite::Error::FromSqlConversionFailure(
+                column,
+                Type::Text,
+                format!("Unknown webhook inbox status {}", value).into(),
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct InboxEvent {
+    pub id: i64,
+    pub payload: WebhookRequest,
+    pub status: InboxStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: String,
+    pub created_at: String,
+}
+
+impl InboxEvent {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let payload: String = row.get(1)?;
+        let status: String = row.get(2)?;
+        Ok(InboxEvent {
+            id: row.get(0)?,
+            payload: from_json(1, &payload)?,
+            status: InboxStatus::from_column(2, &status)?,
+            attempts: row.get(3)?,
+            last_error: row.get(4)?,
+            next_attempt_at: row.get(5)?,
+            created_at: row.get(6)?,
+        })
+    }
+}
+
+const COLUMNS: &str = "id, payload, status, attempts, last_error, next_attempt_at, created_at";
+
+/// Durable inbox of verified MailerSend webhooks, applied to listmonk in the
+/// background so that webhooks are not lost while listmonk is unavailable.
+/// Events being processed when the service stopped are retried after a
+/// restart.
+#[derive(Clone)]
+pub struct WebhookInbox {
+    database: Database,
+}
+
+impl WebhookInbox {
+    pub fn new(database: Database) -> rusqlite::Result<Self> {
+        let connection = database.connection();
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS webhook_inbox (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                payload TEXT NOT NULL,
+                status TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                last_error TEXT,
+                next_attempt_at TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        let replayed = connection.execute(
+            "UPDATE webhook_inbox SET status = 'pending' WHERE status = 'processing'",
+            [],
+        )?;
+        if replayed > 0 {
+            log::info!("Replaying {} unprocessed webhooks", replayed);
+        }
+        drop(connection);
+        Ok(WebhookInbox { database })
+    }
+
+    pub fn push(&self, payload: &WebhookRequest, now: DateTime<Utc>) -> rusqlite::Result<i64> {
+        let connection = self.database.connection();
+        connection.execute(
+            "INSERT INTO webhook_inbox (payload, status, next_attempt_at, created_at)
+             VALUES (?1, 'pending', ?2, ?2)",
+            params![to_json(payload)?, timestamp(now)],
+        )?;
+        Ok(connection.last_insert_rowid())
+    }
+
+    /// Returns the pending events due at `now` in arrival order and marks
+    /// them as being processed.
+    pub fn claim_due(&self, now: DateTime<Utc>) -> rusqlite::Result<Vec<InboxEvent>> {
+        let connection = self.database.connection();
+        let mut statement = connection.prepare(&format!(
+            "UPDATE webhook_inbox SET status = 'processing'
+             WHERE status = 'pending' AND next_attempt_at <= ?1 RETURNING {}",
+            COLUMNS
+        ))?;
+        let mut result = statement
+            .query_map([timestamp(now)], InboxEvent::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        result.sort_by_key(|x| x.id);
+        Ok(result)
+    }
+
+    /// Removes an event applied to listmonk.
+    pub fn complete(&self, id: i64) -> rusqlite::Result<()> {
+        self.database
+            .connection()
+            .execute("DELETE FROM webhook_inbox WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    /// Returns a failed attempt back to the inbox until `next_attempt_at`.
+    pub fn retry_at(
+        &self,
+        id: i64,
+        error: &str,
+        next_attempt_at: DateTime<Utc>,
+    ) -> rusqlite::Result<()> {
+        self.database.connection().execute(
+            "UPDATE webhook_inbox
+             SET status = 'pending', attempts = attempts + 1, last_error = ?2, next_attempt_at = ?3
+             WHERE id = ?1",
+            params![id, error, timestamp(next_attempt_at)],
+        )?;
+        Ok(())
+    }
+
+    /// Keeps an event that cannot be applied for inspection.
+    pub fn fail(&self, id: i64, error: &str) -> rusqlite::Result<()> {
+        self.database.connection().execute(
+            "UPDATE webhook_inbox
+             SET status = 'failed', attempts = attempts + 1, last_error = ?2
+             WHERE id = ?1",
+            params![id, error],
+        )?;
+        Ok(())
+    }
+
+    /// Returns a failed event to the inbox. Returns whether a failed event
+    /// with the id existed.
+    pub fn requeue(&self, id: i64, now: DateTime<Utc>) -> rusqlite::Result<bool> {
+        let updated = self.database.connection().execute(
+            "UPDATE webhook_inbox SET status = 'pending', attempts = 0, next_attempt_at = ?2
+             WHERE id = ?1 AND status = 'failed'",
+            params![id, timestamp(now)],
+        )?;
+        Ok(updated > 0)
+    }
+
+    pub fn list(
+        &self,
+        status: Option<InboxStatus>,
+        limit: u32,
+        offset: u32,
+    ) -> rusqlite::Result<Vec<InboxEvent>> {
+        let connection = self.database.connection();
+        let mut statement = connection.prepare(&format!(
+            "SELECT {} FROM webhook_inbox WHERE ?1 IS NULL OR status = ?1
+             ORDER BY id LIMIT ?2 OFFSET ?3",
+            COLUMNS
+        ))?;
+        let result = statement
+            .query_map(
+                params![status.map(|x| x.as_str()), limit, offset],
+                InboxEvent::from_row,
+            )?
+            .collect();
+        result
+    }
+
+    pub fn get(&self, id: i64) -> rusqlite::Result<Option<InboxEvent>> {
+        self.database
+            .connection()
+            .query_row(
+                &format!("SELECT {} FROM webhook_inbox WHERE id = ?1", COLUMNS),
+                [id],
+                InboxEvent::from_row,
+            )
+            .optional()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    const BODY: &str = include_str!("../../test/req_bounce.json");
+
+    fn payload() -> WebhookRequest {
+        serde_json::from_str(BODY).unwrap()
+    }
+
+    #[test]
+    fn test_webhook_inbox() {
+        let inbox = WebhookInbox::new(Database::open_in_memory().unwrap()).unwrap();
+        let now = Utc::now();
+        let first = inbox.push(&payload(), now).unwrap();
+        let second = inbox.push(&payload(), now).unwrap();
+
+        let due = inbox.claim_due(now).unwrap();
+        assert_eq!(
+            due.iter().map(|x| x.id).collect::<Vec<_>>(),
+            [first, second]
+        );
+        assert_eq!(due[0].status, InboxStatus::Processing);
+        assert!(inbox.claim_due(now).unwrap().is_empty());
+
+        inbox.complete(first).unwrap();
+        inbox
+            .retry_at(second, "listmonk is down", now + Duration::minutes(1))
+            .unwrap();
+        assert!(inbox.get(first).unwrap().is_none());
+        assert!(inbox.claim_due(now).unwrap().is_empty());
+
+        let due = inbox.claim_due(now + Duration::minutes(1)).unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].attempts, 1);
+        assert_eq!(due[0].last_error.as_deref(), Some("listmonk is down"));
+    }
+
+    #[test]
+    fn test_failed_webhooks_are_kept_until_requeued() {
+        let inbox = WebhookInbox::new(Database::open_in_memory().unwrap()).unwrap();
+        let now = Utc::now();
+        let id = inbox.push(&payload(), now).unwrap();
+        inbox.push(&payload(), now).unwrap();
+        inbox.claim_due(now).unwrap();
+        inbox.fail(id, "Invalid recipient address").unwrap();
+
+        let failed = inbox.list(Some(InboxStatus::Failed), 10, 0).unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].id, id);
+        assert_eq!(inbox.list(None, 10, 0).unwrap().len(), 2);
+
+        assert!(inbox.requeue(id, now).unwrap());
+        assert!(!inbox.requeue(id, now).unwrap());
+        assert_eq!(inbox.claim_due(now).unwrap()[0].id, id);
+    }
+
+    #[test]
+    fn test_processing_webhooks_are_replayed_after_restart() {
+        let database = Database::open_in_memory().unwrap();
+        let inbox = WebhookInbox::new(database.clone()).unwrap();
+        let now = Utc::now();
+        inbox.push(&payload(), now).unwrap();
+        inbox.claim_due(now).unwrap();
+
+        let inbox = WebhookInbox::new(database).unwrap();
+        assert_eq!(inbox.claim_due(now).unwrap().len(), 1);
+    }
+}
@@ -1,11 +1,25 @@
 use actix_jobs::Job;
+use actix_web::ResponseError;
+use chrono::Utc;
 
-use super::{api::MailerSendAPI, buffer::Buffer};
+use super::{
+    api::{Email, MailerSendAPI},
+    buffer::Buffer,
+    bulk_status::{BulkEmailStatus, BulkEmailStore, TrackedBulkEmail},
+    dead_letter::DeadLetterStore,
+    delivery_log::{DeliveryLog, SEND_ACCEPTED, SEND_FAILED, SEND_INVALID, SEND_REJECTED},
+    inbox::WebhookInbox,
+    retry::{Outcome, RetryPolicy},
+    webhook::WebhookProcessor,
+};
 
 pub struct OutgoingEmailsJob {
     cron: String,
     mailersend_api: MailerSendAPI,
     emails_buffer: Buffer,
+    bulk_emails: BulkEmailStore,
+    dead_letters: DeadLetterStore,
+    delivery_log: DeliveryLog,
     bulk_size: usize,
 }
 
@@ -14,12 +28,18 @@ impl OutgoingEmailsJob {
         cron: &str,
         mailersend_api: MailerSendAPI,
         emails_buffer: Buffer,
+        bulk_emails: BulkEmailStore,
+        dead_letters: DeadLetterStore,
+        delivery_log: DeliveryLog,
         bulk_size: usize,
     ) -> Self {
         OutgoingEmailsJob {
             cron: cron.to_string(),
             mailersend_api,
             emails_buffer,
+            bulk_emails,
+            dead_letters,
+            delivery_log,
             bulk_size,
         }
     }
@@ -34,16 +54,617 @@ impl Job for OutgoingEmailsJob {
         let emails_buffer = self.emails_buffer.clone();
         let bulk_size = self.bulk_size;
         let mailersend_api = self.mailersend_api.clone();
+        let bulk_emails = self.bulk_emails.clone();
+        let dead_letters = self.dead_letters.clone();
+        let delivery_log = self.delivery_log.clone();
         actix_rt::spawn(async move {
-            let emails = emails_buffer.pop_all().await;
-            if emails.is_empty() {
+            let entries = match emails_buffer.pop_all().await {
+                Ok(entries) => entries,
+                Err(err) => {
+                    log::error!("Failed to read cached emails due to error: {}", err);
+                    return;
+                }
+            };
+            if entries.is_empty() {
                 return;
             }
-            log::info!("Sending {} cached emails", emails.len());
-            match mailersend_api.send_bulk(emails, bulk_size).await {
-                Ok(_) => log::info!("Successfully sent cached emails"),
-                Err(err) => log::error!("Failed to cached emails due to error: {}", err),
+            log::info!("Sending {} cached emails", entries.len());
+            let emails: Vec<Email> = entries.iter().map(|x| x.email.clone()).collect();
+            let results = mailersend_api.send_bulk(emails, bulk_size).await;
+            for (range, result) in results {
+                let chunk = &entries[range];
+                let ids: Vec<i64> = chunk.iter().map(|x| x.id).collect();
+                let (event, details) = match &result {
+                    Ok(res) if res.outcome() == Outcome::Success => (
+                        SEND_ACCEPTED,
+                        serde_json::json!({"bulk_email_id": res.bulk_email_id()}),
+                    ),
+                    Ok(res) if res.outcome() == Outcome::Permanent => (
+                        SEND_REJECTED,
+                        serde_json::json!({
+                            "status": res.api_response_status,
+                            "error": serde_json::from_str::<serde_json::Value>(&res.api_response_body)
+                                .unwrap_or_else(|_| res.api_response_body.clone().into()),
+                        }),
+                    ),
+                    Ok(res) => (
+                        SEND_FAILED,
+                        serde_json::json!({
+                            "status": res.api_response_status,
+                            "error": res.api_response_message,
+                        }),
+                    ),
+                    Err(err) => (SEND_FAILED, serde_json::json!({"error": err.to_string()})),
+                };
+                let emails: Vec<Email> = chunk.iter().map(|x| x.email.clone()).collect();
+                if let Err(err) = delivery_log.add_sends(&emails, event, details) {
+                    log::error!("Failed to log send attempt due to error: {}", err);
+                }
+                let update = match result {
+                    Ok(res) if res.outcome() == Outcome::Success => {
+                        log::info!("Successfully sent {} cached emails", ids.len());
+                        match res.bulk_email_id() {
+                            Some(bulk_email_id) => {
+                                let emails: Vec<Email> =
+                                    chunk.iter().map(|x| x.email.clone()).collect();
+                                if let Err(err) = bulk_emails.track(&bulk_email_id, &emails) {
+                                    log::error!(
+                                        "Failed to track bulk email {} due to error: {}",
+                                        bulk_email_id,
+                                        err
+                                    );
+                                }
+                            }
+                            None => log::warn!(
+                                "MailerSend API response without bulk_email_id: {}",
+                                res.api_response_body
+                            ),
+                        }
+                        emails_buffer.ack(&ids).await
+                    }
+                    Ok(res) if res.outcome() == Outcome::Permanent => {
+                        log::error!(
+                            "MailerSend rejected {} cached emails: {} {}",
+                            ids.len(),
+                            res.api_response_status,
+                            res.api_response_body
+                        );
+                        let emails = chunk.iter().map(|x| x.email.clone()).collect();
+                        match dead_letters.add_all(
+                            emails,
+                            res.api_response_status,
+                            &res.api_response_body,
+                        ) {
+                            Ok(_) => emails_buffer.ack(&ids).await,
+                            Err(err) => {
+                                log::error!("Failed to store dead letters due to error: {}", err);
+                                emails_buffer.release(&ids).await
+                            }
+                        }
+                    }
+                    Ok(res) if res.outcome() == Outcome::Unauthorized => {
+                        log::error!(
+                            "MailerSend rejected the API token: {} {}, check its validity and permissions",
+                            res.api_response_status,
+                            res.api_response_body
+                        );
+                        log::warn!("Re-enqueuing {} cached emails", ids.len());
+                        emails_buffer.release(&ids).await
+                    }
+                    Ok(res) => {
+                        log::error!(
+                            "Failed to send cached emails, MailerSend API response: {} {}",
+                            res.api_response_status,
+                            res.api_response_message
+                        );
+                        log::warn!("Re-enqueuing {} cached emails", ids.len());
+                        emails_buffer.release(&ids).await
+                    }
+                    Err(err) => {
+                        log::error!("Failed to send cached emails due to error: {}", err);
+                        log::warn!("Re-enqueuing {} cached emails", ids.len());
+                        emails_buffer.release(&ids).await
+                    }
+                };
+                if let Err(err) = update {
+                    log::error!("Failed to update cached emails due to error: {}", err);
+                }
             }
         });
     }
 }
+
+pub struct BulkStatusJob {
+    cron: String,
+    mailersend_api: MailerSendAPI,
+    bulk_emails: BulkEmailStore,
+    dead_letters: DeadLetterStore,
+    delivery_log: DeliveryLog,
+}
+
+impl BulkStatusJob {
+    pub fn new(
+        cron: &str,
+        mailersend_api: MailerSendAPI,
+        bulk_emails: BulkEmailStore,
+        dead_letters: DeadLetterStore,
+        delivery_log: DeliveryLog,
+    ) -> Self {
+        BulkStatusJob {
+            cron: cron.to_string(),
+            mailersend_api,
+            bulk_emails,
+            dead_letters,
+            delivery_log,
+        }
+    }
+}
+
+impl Job for BulkStatusJob {
+    fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    fn run(&mut self) {
+        let mailersend_api = self.mailersend_api.clone();
+        let bulk_emails = self.bulk_emails.clone();
+        let dead_letters = self.dead_letters.clone();
+        let delivery_log = self.delivery_log.clone();
+        actix_rt::spawn(async move {
+            poll_bulk_statuses(&mailersend_api, &bulk_emails, &dead_letters, &delivery_log).await;
+        });
+    }
+}
+
+/// Polls the status of the tracked bulk requests and keeps the emails that
+/// failed validation as dead letters. Bulk requests being polled by an
+/// earlier, still running poll are skipped.
+pub async fn poll_bulk_statuses(
+    mailersend_api: &MailerSendAPI,
+    bulk_emails: &BulkEmailStore,
+    dead_letters: &DeadLetterStore,
+    delivery_log: &DeliveryLog,
+) {
+    let pending = match bulk_emails.claim_pending() {
+        Ok(pending) => pending,
+        Err(err) => {
+            log::error!("Failed to read tracked bulk emails due to error: {}", err);
+            return;
+        }
+    };
+    for tracked in pending {
+        let status = match mailersend_api.get_bulk_status(&tracked.bulk_email_id).await {
+            Ok(status) if status.is_completed() => status,
+            Ok(status) => {
+                log::info!(
+                    "Bulk email {} is still {}",
+                    tracked.bulk_email_id,
+                    status.state
+                );
+                release_bulk_email(bulk_emails, &tracked.bulk_email_id);
+                continue;
+            }
+            Err(err) => {
+                log::error!(
+                    "Failed to get status of bulk email {} due to error: {}",
+                    tracked.bulk_email_id,
+                    err
+                );
+                release_bulk_email(bulk_emails, &tracked.bulk_email_id);
+                continue;
+            }
+        };
+        log::info!(
+            "Bulk email {} {}: {} recipients, {} messages, {} suppressed, {} validation errors",
+            tracked.bulk_email_id,
+            status.state,
+            status.total_recipients_count.unwrap_or_default(),
+            status.messages_id.as_ref().map_or(0, |x| x.len()),
+            status.suppressed_recipients_count.unwrap_or_default(),
+            status.validation_errors_count.unwrap_or_default()
+        );
+        for (index, error) in status.validation_errors_by_index() {
+            let email = match tracked.emails.get(index) {
+                Some(email) => email,
+                None => {
+                    log::error!(
+                        "Bulk email {} validation error for unknown message {}: {}",
+                        tracked.bulk_email_id,
+                        index,
+                        error
+                    );
+                    continue;
+                }
+            };
+            let recipients: Vec<&str> = email.to.iter().map(|x| x.email()).collect();
+            log::error!(
+                "Bulk email {} failed validation for {}: {}",
+                tracked.bulk_email_id,
+                recipients.join(", "),
+                error
+            );
+            if let Err(err) = dead_letters.add_all(vec![email.clone()], 422, &error.to_string()) {
+                log::error!("Failed to store dead letter due to error: {}", err);
+            }
+            if let Err(err) = delivery_log.add_sends(
+                std::slice::from_ref(email),
+                SEND_INVALID,
+                serde_json::json!({
+                    "bulk_email_id": tracked.bulk_email_id,
+                    "errors": error,
+                }),
+            ) {
+                log::error!("Failed to log invalid email due to error: {}", err);
+            }
+        }
+        set_message_ids(&tracked, &status, delivery_log);
+        if let Err(err) = bulk_emails.complete(&tracked.bulk_email_id) {
+            log::error!(
+                "Failed to complete bulk email {} due to error: {}",
+                tracked.bulk_email_id,
+                err
+            );
+        }
+    }
+}
+
+/// Backfills the message ids of the accepted emails of a completed bulk
+/// request. MailerSend lists them in request order, without the emails that
+/// failed validation.
+fn set_message_ids(
+    tracked: &TrackedBulkEmail,
+    status: &BulkEmailStatus,
+    delivery_log: &DeliveryLog,
+) {
+    let message_ids = status.messages_id.as_deref().unwrap_or_default();
+    let errors = status.validation_errors_by_index();
+    let valid: Vec<&Email> = tracked
+        .emails
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !errors.contains_key(index))
+        .map(|(_, email)| email)
+        .collect();
+    if valid.len() != message_ids.len() {
+        log::warn!(
+            "Bulk email {} has {} message ids for {} valid emails, not logging them",
+            tracked.bulk_email_id,
+            message_ids.len(),
+            valid.len()
+        );
+        return;
+    }
+    let messages: Vec<(&Email, &str)> = valid
+        .into_iter()
+        .zip(message_ids.iter().map(|x| x.as_str()))
+        .collect();
+    if let Err(err) = delivery_log.set_message_ids(&tracked.bulk_email_id, &messages) {
+        log::error!("Failed to log message ids due to error: {}", err);
+    }
+}
+
+fn release_bulk_email(bulk_emails: &BulkEmailStore, bulk_email_id: &str) {
+    if let Err(err) = bulk_emails.release(bulk_email_id) {
+        log::error!(
+            "Failed to release bulk email {} due to error: {}",
+            bulk_email_id,
+            err
+        );
+    }
+}
+
+pub struct DeliveryLogPruneJob {
+    cron: String,
+    delivery_log: DeliveryLog,
+    retention: chrono::Duration,
+}
+
+impl DeliveryLogPruneJob {
+    pub fn new(cron: &str, delivery_log: DeliveryLog, retention: chrono::Duration) -> Self {
+        DeliveryLogPruneJob {
+            cron: cron.to_string(),
+            delivery_log,
+            retention,
+        }
+    }
+}
+
+impl Job for DeliveryLogPruneJob {
+    fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    fn run(&mut self) {
+        let delivery_log = self.delivery_log.clone();
+        let before = Utc::now() - self.retention;
+        actix_rt::spawn(async move {
+            match delivery_log.prune(before) {
+                Ok(pruned) => log::info!("Pruned {} delivery events before {}", pruned, before),
+                Err(err) => log::error!("Failed to prune delivery events due to error: {}", err),
+            }
+        });
+    }
+}
+
+pub struct WebhookInboxJob {
+    cron: String,
+    webhook_inbox: WebhookInbox,
+    webhook_processor: WebhookProcessor,
+    retry_policy: RetryPolicy,
+}
+
+impl WebhookInboxJob {
+    pub fn new(
+        cron: &str,
+        webhook_inbox: WebhookInbox,
+        webhook_processor: WebhookProcessor,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        WebhookInboxJob {
+            cron: cron.to_string(),
+            webhook_inbox,
+            webhook_processor,
+            retry_policy,
+        }
+    }
+}
+
+impl Job for WebhookInboxJob {
+    fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    fn run(&mut self) {
+        let webhook_inbox = self.webhook_inbox.clone();
+        let webhook_processor = self.webhook_processor.clone();
+        let retry_policy = self.retry_policy.clone();
+        actix_rt::spawn(async move {
+            process_webhook_inbox(&webhook_inbox, &webhook_processor, &retry_policy).await;
+        });
+    }
+}
+
+/// Applies the due webhooks from the inbox to listmonk. Webhooks failing
+/// with a server error, such as listmonk being unavailable, are retried
+/// with backoff, while the others are kept as failed.
+pub async fn process_webhook_inbox(
+    webhook_inbox: &WebhookInbox,
+    webhook_processor: &WebhookProcessor,
+    retry_policy: &RetryPolicy,
+) {
+    let events = match webhook_inbox.claim_due(Utc::now()) {
+        Ok(events) => events,
+        Err(err) => {
+            log::error!("Failed to read webhook inbox due to error: {}", err);
+            return;
+        }
+    };
+    for event in events {
+        let attempt = event.attempts + 1;
+        let update = match webhook_processor.process(&event.payload).await {
+            Ok(_) => webhook_inbox.complete(event.id),
+            Err(err)
+                if err.status_code().is_server_error() && attempt < retry_policy.max_attempts =>
+            {
+                let delay = retry_policy.delay(attempt, None);
+                log::warn!(
+                    "Retrying webhook {} in {:?} after attempt {} of {}: {}",
+                    event.id,
+                    delay,
+                    attempt,
+                    retry_policy.max_attempts,
+                    err
+                );
+                let next_attempt_at = Utc::now()
+                    + chrono::Duration::from_std(delay)
+                        .unwrap_or_else(|_| chrono::Duration::zero());
+                webhook_inbox.retry_at(event.id, &err.to_string(), next_attempt_at)
+            }
+            Err(err) => {
+                log::error!(
+                    "Failed to process webhook {} after {} attempts: {}",
+                    event.id,
+                    attempt,
+                    err
+                );
+                webhook_inbox.fail(event.id, &err.to_string())
+            }
+        };
+        if let Err(err) = update {
+            log::error!("Failed to update webhook inbox due to error: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::listmonk::{api::ListmonkAPI, mock};
+    use crate::mailersend::{
+        bounce_policy::BouncePolicy,
+        event::WebhookRequest,
+        inbox::InboxStatus,
+        mock::{self as mailersend_mock, MockResponse},
+        rate_limiter::RateLimiter,
+    };
+    use crate::storage::Database;
+
+    const BODY: &str = include_str!("../../test/req_bounce.json");
+
+    fn processor(listmonk_api: ListmonkAPI, database: &Database) -> WebhookProcessor {
+        WebhookProcessor::for_test(listmonk_api, database).with_bounce_policy(
+            BouncePolicy::new(database.clone(), chrono::Duration::hours(72), 0, None).unwrap(),
+        )
+    }
+
+    fn payload(body: &str) -> WebhookRequest {
+        serde_json::from_str(body).unwrap()
+    }
+
+    fn retry_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy::new(max_attempts, Duration::ZERO, Duration::ZERO)
+    }
+
+    fn email(to: &str) -> Email {
+        Email::for_test(to).with_tags(&["campaign:a4d516f2"])
+    }
+
+    fn bulk_status(status: serde_json::Value) -> MailerSendAPI {
+        let (endpoint, _) = mailersend_mock::mailersend(&[(
+            "GET /bulk-email/bulk-1",
+            MockResponse::new(200, serde_json::json!({ "data": status })),
+        )]);
+        MailerSendAPI::new(
+            &endpoint,
+            "token",
+            RateLimiter::per_minute(600, 10),
+            retry_policy(1),
+        )
+    }
+
+    #[actix_rt::test]
+    async fn test_bulk_validation_errors_are_dead_lettered_once() {
+        let database = Database::open_in_memory().unwrap();
+        let bulk_emails = BulkEmailStore::new(database.clone()).unwrap();
+        let dead_letters = DeadLetterStore::new(database.clone()).unwrap();
+        let delivery_log = DeliveryLog::new(database.clone()).unwrap();
+        let api = bulk_status(serde_json::json!({
+            "state": "completed",
+            "validation_errors_count": 1,
+            "validation_errors": {"message.1.subject": ["The subject is required."]},
+            "messages_id": ["62fb66bef54a112e920b5493"]
+        }));
+        bulk_emails
+            .track("bulk-1", &[email("a@email.com"), email("b@email.com")])
+            .unwrap();
+
+        futures::join!(
+            poll_bulk_statuses(&api, &bulk_emails, &dead_letters, &delivery_log),
+            poll_bulk_statuses(&api, &bulk_emails, &dead_letters, &delivery_log),
+        );
+        poll_bulk_statuses(&api, &bulk_emails, &dead_letters, &delivery_log).await;
+        let dead = dead_letters.list(10, 0).unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].email.to[0].email(), "b@email.com");
+        assert!(bulk_emails.claim_pending().unwrap().is_empty());
+        let timeline = delivery_log
+            .recipient_timeline("b@email.com", 10, 0)
+            .unwrap();
+        assert_eq!(timeline[0].event.event, SEND_INVALID);
+    }
+
+    #[actix_rt::test]
+    async fn test_completed_bulk_emails_log_message_ids() {
+        let database = Database::open_in_memory().unwrap();
+        let bulk_emails = BulkEmailStore::new(database.clone()).unwrap();
+        let dead_letters = DeadLetterStore::new(database.clone()).unwrap();
+        let delivery_log = DeliveryLog::new(database.clone()).unwrap();
+        let api = bulk_status(serde_json::json!({
+            "state": "completed",
+            "validation_errors_count": 1,
+            "validation_errors": {"message.1.subject": ["The subject is required."]},
+            "messages_id": ["62fb66bef54a112e920b5493", "62fb66bef54a112e920b5494"]
+        }));
+        let emails = [
+            email("a@email.com"),
+            email("b@email.com"),
+            email("c@email.com"),
+        ];
+        bulk_emails.track("bulk-1", &emails).unwrap();
+        delivery_log
+            .add_sends(
+                &emails,
+                SEND_ACCEPTED,
+                serde_json::json!({"bulk_email_id": "bulk-1"}),
+            )
+            .unwrap();
+
+        poll_bulk_statuses(&api, &bulk_emails, &dead_letters, &delivery_log).await;
+        let message_id = |recipient| {
+            delivery_log
+                .recipient_timeline(recipient, 10, 0)
+                .unwrap()
+                .into_iter()
+                .find(|x| x.event.event == SEND_ACCEPTED)
+                .unwrap()
+                .event
+                .message_id
+        };
+        assert_eq!(
+            message_id("a@email.com").as_deref(),
+            Some("62fb66bef54a112e920b5493")
+        );
+        assert_eq!(message_id("b@email.com"), None);
+        assert_eq!(
+            message_id("c@email.com").as_deref(),
+            Some("62fb66bef54a112e920b5494")
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_unfinished_bulk_emails_are_polled_again() {
+        let database = Database::open_in_memory().unwrap();
+        let bulk_emails = BulkEmailStore::new(database.clone()).unwrap();
+        let dead_letters = DeadLetterStore::new(database.clone()).unwrap();
+        let delivery_log = DeliveryLog::new(database.clone()).unwrap();
+        let api = bulk_status(serde_json::json!({"state": "processing"}));
+        bulk_emails
+            .track("bulk-1", &[email("a@email.com")])
+            .unwrap();
+
+        poll_bulk_statuses(&api, &bulk_emails, &dead_letters, &delivery_log).await;
+        assert_eq!(bulk_emails.claim_pending().unwrap().len(), 1);
+        assert!(dead_letters.list(10, 0).unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_processed_webhooks_are_removed_from_inbox() {
+        let database = Database::open_in_memory().unwrap();
+        let inbox = WebhookInbox::new(database.clone()).unwrap();
+        let (api, requests) = mock::listmonk(&[]);
+        inbox.push(&payload(BODY), Utc::now()).unwrap();
+
+        process_webhook_inbox(&inbox, &processor(api, &database), &retry_policy(3)).await;
+        assert_eq!(requests.find("POST /webhooks/bounce").len(), 1);
+        assert!(inbox.list(None, 10, 0).unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_webhooks_are_retried_while_listmonk_is_unavailable() {
+        let database = Database::open_in_memory().unwrap();
+        let inbox = WebhookInbox::new(database.clone()).unwrap();
+        let api = ListmonkAPI::new("http://127.0.0.1:1", "user", "password");
+        let processor = processor(api, &database);
+        let id = inbox.push(&payload(BODY), Utc::now()).unwrap();
+
+        process_webhook_inbox(&inbox, &processor, &retry_policy(2)).await;
+        let event = inbox.get(id).unwrap().unwrap();
+        assert_eq!(event.status, InboxStatus::Pending);
+        assert_eq!(event.attempts, 1);
+        assert!(event.last_error.is_some());
+
+        process_webhook_inbox(&inbox, &processor, &retry_policy(2)).await;
+        let event = inbox.get(id).unwrap().unwrap();
+        assert_eq!(event.status, InboxStatus::Failed);
+        assert_eq!(event.attempts, 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_invalid_webhooks_are_not_retried() {
+        let database = Database::open_in_memory().unwrap();
+        let inbox = WebhookInbox::new(database.clone()).unwrap();
+        let (api, requests) = mock::listmonk(&[]);
+        let body = BODY
+            .replace("activity.soft_bounced", "activity.spam_complaint")
+            .replace("sober.pl@gmail.com", "not-an-email");
+        let id = inbox.push(&payload(&body), Utc::now()).unwrap();
+
+        process_webhook_inbox(&inbox, &processor(api, &database), &retry_policy(5)).await;
+        let event = inbox.get(id).unwrap().unwrap();
+        assert_eq!(event.status, InboxStatus::Failed);
+        assert_eq!(event.attempts, 1);
+        assert!(requests.all().is_empty());
+    }
+}
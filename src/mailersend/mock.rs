@@ -0,0 +1,78 @@
+//! A local HTTP server standing in for the MailerSend API in tests.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use actix_web::{http::StatusCode, web, App, HttpRequest, HttpResponse, HttpServer};
+
+use crate::listmonk::mock::Requests;
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: serde_json::Value,
+}
+
+impl MockResponse {
+    pub fn new(status: u16, body: serde_json::Value) -> Self {
+        MockResponse {
+            status,
+            headers: vec![],
+            body,
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+type Responses = Arc<Mutex<HashMap<String, Vec<MockResponse>>>>;
+
+async fn respond(
+    request: HttpRequest,
+    body: web::Bytes,
+    requests: web::Data<Requests>,
+    responses: web::Data<Responses>,
+) -> HttpResponse {
+    let route = format!("{} {}", request.method(), request.path());
+    requests.record(&request, &body);
+    let response = match responses.lock().unwrap().get_mut(&route) {
+        Some(queue) if queue.len() > 1 => queue.remove(0),
+        Some(queue) => queue[0].clone(),
+        None => MockResponse::new(404, serde_json::json!({"message": "Not found"})),
+    };
+    let mut builder = HttpResponse::build(StatusCode::from_u16(response.status).unwrap());
+    for header in response.headers {
+        builder.insert_header(header);
+    }
+    builder.json(response.body)
+}
+
+/// Starts a server answering each `METHOD /path` route with the given
+/// responses in order, repeating the last one, and returns its endpoint.
+pub fn mailersend(responses: &[(&str, MockResponse)]) -> (String, Requests) {
+    let requests = Requests::default();
+    let mut routes: HashMap<String, Vec<MockResponse>> = HashMap::new();
+    for (route, response) in responses {
+        routes
+            .entry(route.to_string())
+            .or_default()
+            .push(response.clone());
+    }
+    let responses: Responses = Arc::new(Mutex::new(routes));
+    let server_requests = requests.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(server_requests.clone()))
+            .app_data(web::Data::new(responses.clone()))
+            .default_service(web::to(respond))
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let address = server.addrs()[0];
+    actix_rt::spawn(server.run());
+    (format!("http://{}", address), requests)
+}
@@ -1,5 +1,17 @@
 pub mod api;
+pub mod bounce_policy;
 pub mod buffer;
+pub mod bulk_status;
+pub mod dead_letter;
+pub mod delivery_log;
+pub mod event;
+pub mod inbox;
 pub mod job;
+#[cfg(test)]
+pub mod mock;
+pub mod processed;
+pub mod rate_limiter;
 pub mod rest;
-mod throttler;
+pub mod retry;
+pub mod signature;
+pub mod webhook;
@@ -0,0 +1,92 @@
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, OptionalExtension};
+
+use crate::storage::{timestamp, Database};
+
+/// Ids of the MailerSend webhook events already applied to listmonk, so that
+/// redelivered webhooks are acknowledged without being applied twice. Ids are
+/// forgotten once they are older than the TTL.
+#[derive(Clone)]
+pub struct ProcessedWebhooks {
+    database: Database,
+    ttl: Duration,
+}
+
+impl ProcessedWebhooks {
+    pub fn new(database: Database, ttl: Duration) -> rusqlite::Result<Self> {
+        database.connection().execute(
+            "CREATE TABLE IF NOT EXISTS processed_webhooks (
+                event_id TEXT PRIMARY KEY,
+                processed_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        Ok(ProcessedWebhooks { database, ttl })
+    }
+
+    pub fn contains(&self, event_id: &str, now: DateTime<Utc>) -> rusqlite::Result<bool> {
+        let found = self
+            .database
+            .connection()
+            .query_row(
+                "SELECT 1 FROM processed_webhooks WHERE event_id = ?1 AND processed_at > ?2",
+                params![event_id, timestamp(now - self.ttl)],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    /// Records the event as processed and prunes the expired ids.
+    pub fn add(&self, event_id: &str, now: DateTime<Utc>) -> rusqlite::Result<()> {
+        let mut connection = self.database.connection();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "DELETE FROM processed_webhooks WHERE processed_at <= ?1",
+            [timestamp(now - self.ttl)],
+        )?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO processed_webhooks (event_id, processed_at) VALUES (?1, ?2)",
+            params![event_id, timestamp(now)],
+        )?;
+        transaction.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> ProcessedWebhooks {
+        ProcessedWebhooks::new(Database::open_in_memory().unwrap(), Duration::hours(24)).unwrap()
+    }
+
+    #[test]
+    fn test_processed_webhooks() {
+        let store = store();
+        let now = Utc::now();
+        assert!(!store.contains("62f114f8165fe0d8db0288e5", now).unwrap());
+        store.add("62f114f8165fe0d8db0288e5", now).unwrap();
+        store.add("62f114f8165fe0d8db0288e5", now).unwrap();
+        assert!(store.contains("62f114f8165fe0d8db0288e5", now).unwrap());
+        assert!(!store.contains("62f114f8165fe0d8db0288e6", now).unwrap());
+    }
+
+    #[test]
+    fn test_processed_webhooks_expire() {
+        let store = store();
+        let now = Utc::now();
+        store.add("old", now - Duration::hours(25)).unwrap();
+        assert!(!store.contains("old", now).unwrap());
+
+        store.add("new", now).unwrap();
+        let count: i64 = store
+            .database
+            .connection()
+            .query_row("SELECT COUNT(*) FROM processed_webhooks", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use futures::future::BoxFuture;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+
+use super::retry::parse_retry_after;
+
+/// MailerSend rate limits are enforced over one minute windows.
+pub const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(actix_rt::time::sleep(duration))
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RateLimitHeaders {
+    pub limit: Option<u32>,
+    pub remaining: Option<u32>,
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimitHeaders {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name: &str| headers.get(name).and_then(|x| x.to_str().ok());
+        RateLimitHeaders {
+            limit: header("X-RateLimit-Limit").and_then(|x| x.trim().parse().ok()),
+            remaining: header("X-RateLimit-Remaining").and_then(|x| x.trim().parse().ok()),
+            retry_after: header(RETRY_AFTER.as_str())
+                .and_then(|x| parse_retry_after(x, Utc::now())),
+        }
+    }
+}
+
+struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    tokens: f64,
+    updated_at: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.updated_at = now;
+    }
+
+    /// Takes a token, or returns how long to wait until one is available.
+    fn try_acquire(&mut self, now: Instant) -> Result<(), Duration> {
+        if let Some(paused_until) = self.paused_until {
+            if paused_until > now {
+                return Err(paused_until - now);
+            }
+            self.paused_until = None;
+            self.updated_at = now;
+        }
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - self.tokens) / self.refill_per_sec,
+        ))
+    }
+}
+
+/// Token bucket rate limiter. Tokens refill smoothly at the configured rate
+/// up to the burst capacity, and waiters are served in arrival order.
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<TokenBucket>>,
+    queue: Arc<tokio::sync::Mutex<()>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RateLimiter {
+    pub fn per_minute(requests: u32, burst: u32) -> Self {
+        RateLimiter::with_clock(requests, burst, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(requests_per_minute: u32, burst: u32, clock: Arc<dyn Clock>) -> Self {
+        let capacity = burst.max(1) as f64;
+        RateLimiter {
+            bucket: Arc::new(Mutex::new(TokenBucket {
+                capacity,
+                refill_per_sec: requests_per_minute.max(1) as f64 / 60.0,
+                tokens: capacity,
+                updated_at: clock.now(),
+                paused_until: None,
+            })),
+            queue: Arc::new(tokio::sync::Mutex::new(())),
+            clock,
+        }
+    }
+
+    /// Waits until a request is allowed.
+    pub async fn acquire(&self) {
+        let _turn = self.queue.lock().await;
+        loop {
+            let wait = match self.bucket.lock().unwrap().try_acquire(self.clock.now()) {
+                Ok(()) => return,
+                Err(wait) => wait,
+            };
+            log::debug!("Rate limit reached, waiting {:?}", wait);
+            self.clock.sleep(wait).await;
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Adapts the limiter to the rate limit reported by MailerSend.
+    /// A throttled (429) response pauses all requests until the window resets.
+    pub fn observe(&self, headers: &RateLimitHeaders, throttled: bool) {
+        let now = self.clock.now();
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill(now);
+        if let Some(limit) = headers.limit.filter(|x| *x > 0) {
+            let refill_per_sec = limit as f64 / 60.0;
+            if refill_per_sec != bucket.refill_per_sec {
+                log::info!(
+                    "MailerSend rate limit changed to {} requests per minute",
+                    limit
+                );
+                bucket.refill_per_sec = refill_per_sec;
+            }
+        }
+        if let Some(remaining) = headers.remaining {
+            bucket.tokens = bucket.tokens.min(remaining as f64);
+        }
+        if throttled {
+            let pause = headers.retry_after.unwrap_or(RATE_LIMIT_WINDOW);
+            log::warn!(
+                "MailerSend rate limit exceeded, pausing requests for {:?}",
+                pause
+            );
+            let paused_until = now + pause;
+            if bucket.paused_until < Some(paused_until) {
+                bucket.paused_until = Some(paused_until);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test_clock {
+    use super::*;
+
+    pub struct MockClock {
+        now: Mutex<Instant>,
+    }
+
+    impl MockClock {
+        pub fn new() -> Arc<Self> {
+            Arc::new(MockClock {
+                now: Mutex::new(Instant::now()),
+            })
+        }
+
+        pub fn advance(&self, duration: Duration) {
+            *self.now.lock().unwrap() += duration;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            self.advance(duration);
+            Box::pin(futures::future::ready(()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_clock::MockClock;
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_burst_is_not_delayed() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let limiter = RateLimiter::with_clock(60, 3, clock.clone());
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert_eq!(clock.now(), start);
+    }
+
+    #[actix_rt::test]
+    async fn test_requests_are_spaced_by_refill_rate() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let limiter = RateLimiter::with_clock(30, 1, clock.clone());
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert_eq!(clock.now() - start, Duration::from_secs(4));
+    }
+
+    #[actix_rt::test]
+    async fn test_tokens_refill_up_to_capacity() {
+        let clock = MockClock::new();
+        let limiter = RateLimiter::with_clock(60, 2, clock.clone());
+        limiter.acquire().await;
+        limiter.acquire().await;
+        clock.advance(Duration::from_secs(600));
+        let start = clock.now();
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert_eq!(clock.now() - start, Duration::from_secs(1));
+    }
+
+    #[actix_rt::test]
+    async fn test_observed_limit_changes_refill_rate() {
+        let clock = MockClock::new();
+        let limiter = RateLimiter::with_clock(30, 1, clock.clone());
+        limiter.observe(
+            &RateLimitHeaders {
+                limit: Some(120),
+                ..Default::default()
+            },
+            false,
+        );
+        let start = clock.now();
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert_eq!(clock.now() - start, Duration::from_secs(1));
+    }
+
+    #[actix_rt::test]
+    async fn test_no_remaining_requests_drains_bucket() {
+        let clock = MockClock::new();
+        let limiter = RateLimiter::with_clock(60, 5, clock.clone());
+        limiter.observe(
+            &RateLimitHeaders {
+                limit: Some(60),
+                remaining: Some(0),
+                retry_after: None,
+            },
+            false,
+        );
+        let start = clock.now();
+        limiter.acquire().await;
+        assert_eq!(clock.now() - start, Duration::from_secs(1));
+    }
+
+    #[actix_rt::test]
+    async fn test_throttled_response_pauses_requests() {
+        let clock = MockClock::new();
+        let limiter = RateLimiter::with_clock(60, 5, clock.clone());
+        limiter.observe(
+            &RateLimitHeaders {
+                retry_after: Some(Duration::from_secs(30)),
+                ..Default::default()
+            },
+            true,
+        );
+        let start = clock.now();
+        limiter.acquire().await;
+        assert_eq!(clock.now() - start, Duration::from_secs(30));
+
+        limiter.observe(&RateLimitHeaders::default(), true);
+        let start = clock.now();
+        limiter.acquire().await;
+        assert_eq!(clock.now() - start, RATE_LIMIT_WINDOW);
+    }
+
+    #[test]
+    fn test_rate_limit_headers_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-RateLimit-Limit", "60".parse().unwrap());
+        headers.insert("X-RateLimit-Remaining", "59".parse().unwrap());
+        headers.insert(RETRY_AFTER, "15".parse().unwrap());
+        assert_eq!(
+            RateLimitHeaders::from_headers(&headers),
+            RateLimitHeaders {
+                limit: Some(60),
+                remaining: Some(59),
+                retry_after: Some(Duration::from_secs(15)),
+            }
+        );
+        assert_eq!(
+            RateLimitHeaders::from_headers(&HeaderMap::new()),
+            RateLimitHeaders::default()
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_waiters_are_served_in_order() {
+        let clock = MockClock::new();
+        let limiter = RateLimiter::with_clock(60, 1, clock.clone());
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let waiters = (0..5).map(|i| {
+            let limiter = limiter.clone();
+            let order = order.clone();
+            async move {
+                limiter.acquire().await;
+                order.lock().unwrap().push(i);
+            }
+        });
+        futures::future::join_all(waiters).await;
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2, 3, 4]);
+    }
+}
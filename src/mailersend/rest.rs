@@ -1,120 +1,112 @@
-use crate::{
-    listmonk::api::{BounceType, ListmonkAPI, ListmonkBounce},
-    mailersend::api::EmailAddress,
+use super::{
+    event::WebhookRequest,
+    inbox::WebhookInbox,
+    signature::{SignatureVerifier, SIGNATURE_HEADER},
 };
+use crate::error::RestError;
 
-use actix_web::{web, HttpResponse, Responder, Result};
-use serde::{Deserialize, Serialize};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct RecipientData {
-    object: String,
-    id: String,
-    email: String,
-    created_at: String,
+/// Verifies MailerSend webhooks and stores them in the inbox, from which they
+/// are applied to listmonk in the background.
+pub async fn webhook_handler(
+    webhook_inbox: web::Data<WebhookInbox>,
+    signature_verifier: web::Data<SignatureVerifier>,
+    request: HttpRequest,
+    body: web::Bytes,
+) -> Result<HttpResponse, RestError> {
+    let signature = request
+        .headers()
+        .get(SIGNATURE_HEADER)
+        .and_then(|x| x.to_str().ok());
+    if !signature_verifier.verify(&body, signature) {
+        log::warn!(
+            "Rejecting webhook request with invalid signature from {}",
+            request
+                .connection_info()
+                .realip_remote_addr()
+                .unwrap_or("unknown")
+        );
+        return Err(RestError::InvalidSignature);
+    }
+    let payload = match serde_json::from_slice::<WebhookRequest>(&body) {
+        Ok(payload) => payload,
+        Err(e) => {
+            log::error!("Failed to parse webhook request: {}", e);
+            return Err(RestError::InvalidWebhook(e.to_string()));
+        }
+    };
+    log::info!("Received webhook request: {:?}", payload);
+    let id = webhook_inbox.push(&payload, Utc::now())?;
+    log::info!("Queued webhook request {}", id);
+    Ok(HttpResponse::Ok().body("OK"))
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct EmailData {
-    object: String,
-    id: String,
-    created_at: String,
-    from: String,
-    subject: String,
-    status: String,
-    tags: Option<Vec<String>>,
-    recipient: RecipientData,
-}
-#[derive(Deserialize, Serialize, Debug)]
-pub struct WebhookData {
-    object: String,
-    id: String,
-    #[serde(rename(deserialize = "type"))]
-    data_type: String,
-    created_at: String,
-    email: EmailData,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mailersend::signature::sign;
+    use crate::storage::Database;
+    use actix_web::{http::StatusCode, test::TestRequest, ResponseError};
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct WebhookRequest {
-    #[serde(rename(deserialize = "type"))]
-    request_type: String,
-    domain_id: String,
-    created_at: String,
-    webhook_id: String,
-    url: String,
-    data: WebhookData,
-}
+    const SECRET: &str = "test-signing-secret";
+    const BODY: &str = include_str!("../../test/req_bounce.json");
 
-pub async fn webhook_handler(
-    listmonk_api: web::Data<ListmonkAPI>,
-    payload: web::Json<WebhookRequest>,
-) -> Result<impl Responder> {
-    log::info!("Received webhook request: {:?}", payload);
-    match payload.request_type.as_str() {
-        "activity.soft_bounced" | "activity.hard_bounced" => {
-            handle_bounce(listmonk_api, payload).await
-        }
-        "activity.spam_complaint" => handle_spam_complaint(listmonk_api, payload).await,
-        _ => {
-            log::info!("Ignoring webhook request");
-            Ok(HttpResponse::Ok().body("OK")).into()
-        }
+    fn verifier() -> web::Data<SignatureVerifier> {
+        web::Data::new(SignatureVerifier::new(Some(SECRET.to_string())))
     }
-}
 
-async fn handle_spam_complaint(
-    listmonk_api: web::Data<ListmonkAPI>,
-    payload: web::Json<WebhookRequest>,
-) -> Result<HttpResponse> {
-    log::info!("Received webhook request: {:?}", payload);
-    let recipient_email = &payload.data.email.recipient.email;
-    match listmonk_api
-        .blocklist_by_email(EmailAddress::from_string(&recipient_email).expect("Invalid email"))
-        .await
-    {
-        Ok(_) => {
-            log::info!("Successfully blacklisted recipient");
-            Ok(HttpResponse::Ok().body("OK"))
-        }
-        Err(e) => {
-            log::error!("Failed to blacklist recipient: {}", e);
-            Ok(HttpResponse::InternalServerError().body("Internal Server Error"))
-        }
+    fn inbox() -> web::Data<WebhookInbox> {
+        web::Data::new(WebhookInbox::new(Database::open_in_memory().unwrap()).unwrap())
     }
-}
 
-async fn handle_bounce(
-    listmonk_api: web::Data<ListmonkAPI>,
-    payload: web::Json<WebhookRequest>,
-) -> Result<HttpResponse> {
-    log::info!("Received webhook request: {:?}", payload);
-    let recipient_email = &payload.data.email.recipient.email;
-    let bounce_type = if payload.request_type == "activity.soft_bounced" {
-        BounceType::Soft
-    } else {
-        BounceType::Hard
-    };
-    let capaign_uuid_tag = payload
-        .data
-        .email
-        .tags
-        .as_ref()
-        .and_then(|tags| tags.iter().find(|tag| tag.starts_with("campaign:")))
-        .map(|tag| tag.replace("campaign:", ""));
-    let meta = &payload.data.email.id;
-    let mut listmonk_bounce = ListmonkBounce::new(recipient_email, bounce_type).with_meta(meta);
-    if let Some(campaign_uuid) = capaign_uuid_tag {
-        listmonk_bounce = listmonk_bounce.with_campaign_uuid(&campaign_uuid);
+    fn signed_request(body: &str) -> HttpRequest {
+        TestRequest::default()
+            .insert_header((SIGNATURE_HEADER, sign(SECRET, body.as_bytes())))
+            .to_http_request()
     }
-    match listmonk_api.record_bounce(listmonk_bounce).await {
-        Ok(_) => {
-            log::info!("Successfully recorded bounce event");
-            Ok(HttpResponse::Ok().body("OK"))
-        }
-        Err(e) => {
-            log::error!("Failed to record bounce: {}", e);
-            Ok(HttpResponse::InternalServerError().body("Internal Server Error"))
-        }
+
+    #[actix_rt::test]
+    async fn test_webhook_with_invalid_signature_is_rejected() {
+        let request = TestRequest::default()
+            .insert_header((SIGNATURE_HEADER, sign("other-secret", BODY.as_bytes())))
+            .to_http_request();
+        let inbox = inbox();
+        let error = webhook_handler(inbox.clone(), verifier(), request, BODY.into())
+            .await
+            .unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::UNAUTHORIZED);
+        assert!(inbox.list(None, 10, 0).unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_webhook_without_signature_is_rejected() {
+        let request = TestRequest::default().to_http_request();
+        let error = webhook_handler(inbox(), verifier(), request, BODY.into())
+            .await
+            .unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn test_webhook_with_valid_signature_is_queued() {
+        let inbox = inbox();
+        let response =
+            webhook_handler(inbox.clone(), verifier(), signed_request(BODY), BODY.into())
+                .await
+                .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(inbox.claim_due(Utc::now()).unwrap().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_malformed_webhook_is_rejected() {
+        let body = "{\"type\": \"activity.soft_bounced\"}";
+        let error = webhook_handler(inbox(), verifier(), signed_request(body), body.into())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RestError::InvalidWebhook(_)));
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
     }
 }
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Success,
+    Retryable,
+    /// The API token was rejected. Retrying right away will not help, but
+    /// the request is valid and can be sent once the token is fixed.
+    Unauthorized,
+    Permanent,
+}
+
+impl Outcome {
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            200..=299 => Outcome::Success,
+            408 | 429 | 500..=599 => Outcome::Retryable,
+            401 | 403 => Outcome::Unauthorized,
+            _ => Outcome::Permanent,
+        }
+    }
+
+    pub fn from_error(err: &reqwest::Error) -> Self {
+        if err.is_builder() {
+            Outcome::Permanent
+        } else {
+            Outcome::Retryable
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, base_delay: Duration, max_delay: Duration) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            base_delay,
+            max_delay,
+        }
+    }
+
+    /// Delay before the retry following the given (1-based) failed attempt.
+    /// A server provided `Retry-After` takes precedence over the backoff,
+    /// but is still capped at `max_delay`.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(self.max_delay);
+        }
+        let exponent = attempt.saturating_sub(1).min(31);
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        let half = backoff / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+/// Parses a `Retry-After` header given either in seconds or as an HTTP date.
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outcome_from_status() {
+        assert_eq!(Outcome::from_status(202), Outcome::Success);
+        assert_eq!(Outcome::from_status(429), Outcome::Retryable);
+        assert_eq!(Outcome::from_status(503), Outcome::Retryable);
+        assert_eq!(Outcome::from_status(422), Outcome::Permanent);
+        assert_eq!(Outcome::from_status(401), Outcome::Unauthorized);
+        assert_eq!(Outcome::from_status(403), Outcome::Unauthorized);
+    }
+
+    #[test]
+    fn test_delay_grows_exponentially_with_jitter() {
+        let policy = RetryPolicy::new(5, Duration::from_secs(1), Duration::from_secs(60));
+        for attempt in 1..=4 {
+            let backoff = Duration::from_secs(1 << (attempt - 1));
+            let delay = policy.delay(attempt, None);
+            assert!(delay >= backoff / 2 && delay <= backoff, "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn test_delay_is_capped() {
+        let policy = RetryPolicy::new(50, Duration::from_secs(1), Duration::from_secs(10));
+        assert!(policy.delay(40, None) <= Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_delay_honours_retry_after() {
+        let policy = RetryPolicy::new(5, Duration::from_secs(1), Duration::from_secs(10));
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(5))),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(30))),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2024-01-08T14:46:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(parse_retry_after("12", now), Some(Duration::from_secs(12)));
+        assert_eq!(
+            parse_retry_after("Mon, 08 Jan 2024 14:46:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+}
@@ -0,0 +1,85 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SIGNATURE_HEADER: &str = "Signature";
+
+#[derive(Clone)]
+pub struct SignatureVerifier {
+    signing_secret: Option<String>,
+}
+
+impl SignatureVerifier {
+    pub fn new(signing_secret: Option<String>) -> Self {
+        SignatureVerifier { signing_secret }
+    }
+
+    /// Checks the hex encoded HMAC-SHA256 `signature` of the raw request `body`.
+    /// Every request is accepted when no signing secret is configured.
+    pub fn verify(&self, body: &[u8], signature: Option<&str>) -> bool {
+        let secret = match &self.signing_secret {
+            None => return true,
+            Some(secret) => secret,
+        };
+        let signature = match signature.and_then(|x| hex::decode(x.trim()).ok()) {
+            None => return false,
+            Some(signature) => signature,
+        };
+        let mut mac =
+            HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+        mac.update(body);
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+#[cfg(test)]
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "test-signing-secret";
+    const BODY: &[u8] = include_bytes!("../../test/req_bounce.json");
+
+    #[test]
+    fn test_valid_signature() {
+        let verifier = SignatureVerifier::new(Some(SECRET.to_string()));
+        let signature = sign(SECRET, BODY);
+        assert!(verifier.verify(BODY, Some(&signature)));
+    }
+
+    #[test]
+    fn test_signature_with_wrong_secret() {
+        let verifier = SignatureVerifier::new(Some(SECRET.to_string()));
+        let signature = sign("other-secret", BODY);
+        assert!(!verifier.verify(BODY, Some(&signature)));
+    }
+
+    #[test]
+    fn test_signature_of_tampered_body() {
+        let verifier = SignatureVerifier::new(Some(SECRET.to_string()));
+        let signature = sign(SECRET, BODY);
+        let tampered = String::from_utf8_lossy(BODY).replace("sober.pl@gmail.com", "x@y.com");
+        assert!(!verifier.verify(tampered.as_bytes(), Some(&signature)));
+    }
+
+    #[test]
+    fn test_missing_or_malformed_signature() {
+        let verifier = SignatureVerifier::new(Some(SECRET.to_string()));
+        assert!(!verifier.verify(BODY, None));
+        assert!(!verifier.verify(BODY, Some("not-hex")));
+    }
+
+    #[test]
+    fn test_no_secret_accepts_everything() {
+        let verifier = SignatureVerifier::new(None);
+        assert!(verifier.verify(BODY, None));
+    }
+}
@@ -0,0 +1,793 @@
+use chrono::{DateTime, Utc};
+
+use super::{
+    api::EmailAddress,
+    bounce_policy::{self, BounceAction, BounceClass, BouncePolicy},
+    delivery_log::{DeliveryEvent, DeliveryLog},
+    event::{ActivityData, WebhookData, WebhookEvent, WebhookRequest},
+    processed::ProcessedWebhooks,
+};
+use crate::{
+    config::{UnknownCampaignAction, UnsubscribeAction},
+    error::RestError,
+    listmonk::api::{BounceMeta, BounceType, ListmonkAPI, ListmonkBounce, Subscriber},
+    listmonk::tracking::{
+        self, TrackingPolicy, CAMPAIGN_HEADER, CAMPAIGN_TAG_PREFIX, SUBSCRIBER_HEADER,
+        SUBSCRIBER_TAG_PREFIX,
+    },
+};
+
+/// Applies MailerSend webhook events to listmonk.
+#[derive(Clone)]
+pub struct WebhookProcessor {
+    listmonk_api: ListmonkAPI,
+    unsubscribe_action: UnsubscribeAction,
+    unknown_campaign_action: UnknownCampaignAction,
+    tracking_policy: TrackingPolicy,
+    processed_webhooks: ProcessedWebhooks,
+    bounce_policy: BouncePolicy,
+    delivery_log: DeliveryLog,
+}
+
+impl WebhookProcessor {
+    pub fn new(
+        listmonk_api: ListmonkAPI,
+        unsubscribe_action: UnsubscribeAction,
+        unknown_campaign_action: UnknownCampaignAction,
+        tracking_policy: TrackingPolicy,
+        processed_webhooks: ProcessedWebhooks,
+        bounce_policy: BouncePolicy,
+        delivery_log: DeliveryLog,
+    ) -> Self {
+        WebhookProcessor {
+            listmonk_api,
+            unsubscribe_action,
+            unknown_campaign_action,
+            tracking_policy,
+            processed_webhooks,
+            bounce_policy,
+            delivery_log,
+        }
+    }
+
+    /// Applies the event unless a webhook with the same event id was already
+    /// processed. Failed events are not recorded, so MailerSend can retry them.
+    pub async fn process(&self, payload: &WebhookRequest) -> Result<(), RestError> {
+        let event_id = payload.event_id();
+        if let Some(event_id) = event_id {
+            if self.processed_webhooks.contains(event_id, Utc::now())? {
+                log::info!("Webhook event {} was already processed, ignoring", event_id);
+                return Ok(());
+            }
+        }
+        if let WebhookData::Activity(activity) = &payload.data {
+            self.delivery_log
+                .add_all(&[delivery_event(payload, activity)])?;
+        }
+        self.dispatch(payload).await?;
+        if let Some(event_id) = event_id {
+            self.processed_webhooks.add(event_id, Utc::now())?;
+        }
+        Ok(())
+    }
+
+    async fn dispatch(&self, payload: &WebhookRequest) -> Result<(), RestError> {
+        let activity = match &payload.data {
+            WebhookData::Activity(activity) => activity,
+            WebhookData::Other(_) => {
+                log::info!("Ignoring {} webhook request", payload.event);
+                return Ok(());
+            }
+        };
+        let forward_tracking = self.tracking_policy.is_forwarded();
+        match payload.event {
+            WebhookEvent::SoftBounced => {
+                self.handle_bounce(payload, activity, BounceClass::Soft)
+                    .await
+            }
+            WebhookEvent::HardBounced => {
+                self.handle_bounce(payload, activity, BounceClass::Hard)
+                    .await
+            }
+            WebhookEvent::SpamComplaint => self.blocklist(activity).await,
+            WebhookEvent::Unsubscribed => self.handle_unsubscribe(activity).await,
+            WebhookEvent::Opened if forward_tracking => self.handle_open(activity).await,
+            WebhookEvent::Clicked if forward_tracking => {
+                self.handle_click(activity);
+                Ok(())
+            }
+            _ => {
+                log::info!("Ignoring {} webhook request", payload.event);
+                Ok(())
+            }
+        }
+    }
+
+    async fn blocklist(&self, activity: &ActivityData) -> Result<(), RestError> {
+        let result = match self.find_subscriber_by_uuid(activity).await? {
+            Some(subscriber) => {
+                self.listmonk_api
+                    .blocklist_subscribers(&[subscriber.id])
+                    .await
+            }
+            None => {
+                let recipient_email = &activity.email.recipient.email;
+                let recipient = EmailAddress::from_string(recipient_email)
+                    .map_err(|_| RestError::InvalidRecipient(recipient_email.clone()))?;
+                self.listmonk_api.blocklist_by_email(recipient).await
+            }
+        };
+        match result {
+            Ok(_) => {
+                log::info!("Successfully blacklisted recipient");
+                Ok(())
+            }
+            Err(e) => {
+                log::error!("Failed to blacklist recipient: {}", e);
+                Err(RestError::Listmonk(e.to_string()))
+            }
+        }
+    }
+
+    async fn handle_unsubscribe(&self, activity: &ActivityData) -> Result<(), RestError> {
+        let subscriber = match self.find_subscriber(activity).await? {
+            Some(subscriber) => subscriber,
+            None => {
+                log::warn!("Unsubscribed recipient is not a listmonk subscriber, ignoring");
+                return Ok(());
+            }
+        };
+        let list_ids = match self.unsubscribe_action {
+            UnsubscribeAction::Blocklist => None,
+            UnsubscribeAction::Unsubscribe => {
+                self.lists_to_unsubscribe(activity, &subscriber).await?
+            }
+        };
+        let result = match list_ids {
+            None => {
+                self.listmonk_api
+                    .blocklist_subscribers(&[subscriber.id])
+                    .await
+            }
+            Some(list_ids) if list_ids.is_empty() => {
+                log::info!(
+                    "Subscriber {} has no lists to unsubscribe from",
+                    subscriber.id
+                );
+                return Ok(());
+            }
+            Some(list_ids) => {
+                self.listmonk_api
+                    .unsubscribe_from_lists(&[subscriber.id], &list_ids)
+                    .await
+            }
+        };
+        match result {
+            Ok(_) => {
+                log::info!("Successfully unsubscribed subscriber {}", subscriber.id);
+                Ok(())
+            }
+            Err(e) => {
+                log::error!("Failed to unsubscribe subscriber {}: {}", subscriber.id, e);
+                Err(RestError::Listmonk(e.to_string()))
+            }
+        }
+    }
+
+    /// Lists of the campaign the email was sent for, or the fallback for
+    /// unknown campaigns: all the lists of the subscriber, or `None` when the
+    /// subscriber is to be blocklisted.
+    async fn lists_to_unsubscribe(
+        &self,
+        activity: &ActivityData,
+        subscriber: &Subscriber,
+    ) -> Result<Option<Vec<u64>>, RestError> {
+        let campaign = match campaign_uuid(activity) {
+            None => None,
+            Some(campaign_uuid) => self
+                .listmonk_api
+                .get_campaign_by_uuid(&campaign_uuid)
+                .await
+                .map_err(|e| RestError::Listmonk(e.to_string()))?,
+        };
+        let lists = match (campaign, self.unknown_campaign_action) {
+            (Some(campaign), _) => campaign.lists,
+            (None, UnknownCampaignAction::UnsubscribeAll) => {
+                log::warn!(
+                    "Campaign of unsubscribed email {} not found, unsubscribing from all lists",
+                    activity.email.id
+                );
+                subscriber.lists.clone()
+            }
+            (None, UnknownCampaignAction::Blocklist) => {
+                log::warn!(
+                    "Campaign of unsubscribed email {} not found, blocklisting subscriber {}",
+                    activity.email.id,
+                    subscriber.id
+                );
+                return Ok(None);
+            }
+        };
+        Ok(Some(lists.iter().map(|x| x.id).collect()))
+    }
+
+    async fn handle_open(&self, activity: &ActivityData) -> Result<(), RestError> {
+        let (campaign_uuid, subscriber_uuid) =
+            match (campaign_uuid(activity), subscriber_uuid(activity)) {
+                (Some(campaign_uuid), Some(subscriber_uuid)) => (campaign_uuid, subscriber_uuid),
+                _ => {
+                    log::info!(
+                        "Open of email {} was not sent for a campaign, ignoring",
+                        activity.email.id
+                    );
+                    return Ok(());
+                }
+            };
+        match self
+            .listmonk_api
+            .record_view(&campaign_uuid, &subscriber_uuid)
+            .await
+        {
+            Ok(_) => {
+                log::info!("Successfully recorded campaign view");
+                Ok(())
+            }
+            Err(e) => {
+                log::error!("Failed to record campaign view: {}", e);
+                Err(RestError::Listmonk(e.to_string()))
+            }
+        }
+    }
+
+    /// listmonk can only count clicks on the links it tracks, and it already
+    /// counts those itself when redirecting the subscriber, so clicks are only
+    /// logged.
+    fn handle_click(&self, activity: &ActivityData) {
+        let url = activity
+            .morph
+            .as_ref()
+            .and_then(|x| x.url.as_deref())
+            .unwrap_or_default();
+        if tracking::is_tracked_link(url) {
+            log::info!("Click on {} was counted by listmonk, ignoring", url);
+        } else {
+            log::info!("Click on {} is not tracked by listmonk, ignoring", url);
+        }
+    }
+
+    /// Finds the listmonk subscriber by the subscriber UUID sent along with the email.
+    async fn find_subscriber_by_uuid(
+        &self,
+        activity: &ActivityData,
+    ) -> Result<Option<Subscriber>, RestError> {
+        let subscriber_uuid = match subscriber_uuid(activity) {
+            None => return Ok(None),
+            Some(subscriber_uuid) => subscriber_uuid,
+        };
+        let subscriber = self
+            .listmonk_api
+            .get_subscriber_by_uuid(&subscriber_uuid)
+            .await
+            .map_err(|e| RestError::Listmonk(e.to_string()))?;
+        if subscriber.is_none() {
+            log::warn!(
+                "Subscriber {} not found, looking up by email",
+                subscriber_uuid
+            );
+        }
+        Ok(subscriber)
+    }
+
+    /// Finds the listmonk subscriber of the email, by the subscriber UUID sent
+    /// along with it when available and by the recipient address otherwise.
+    async fn find_subscriber(
+        &self,
+        activity: &ActivityData,
+    ) -> Result<Option<Subscriber>, RestError> {
+        if let Some(subscriber) = self.find_subscriber_by_uuid(activity).await? {
+            return Ok(Some(subscriber));
+        }
+        let recipient_email = &activity.email.recipient.email;
+        let recipient = EmailAddress::from_string(recipient_email)
+            .map_err(|_| RestError::InvalidRecipient(recipient_email.clone()))?;
+        self.listmonk_api
+            .get_subscriber_by_email(&recipient)
+            .await
+            .map_err(|e| RestError::Listmonk(e.to_string()))
+    }
+
+    async fn handle_bounce(
+        &self,
+        payload: &WebhookRequest,
+        activity: &ActivityData,
+        reported: BounceClass,
+    ) -> Result<(), RestError> {
+        let recipient_email = &activity.email.recipient.email;
+        let reason = activity.morph.as_ref().and_then(|x| x.reason.as_deref());
+        let class = BounceClass::classify(reported, reason);
+        let bounce_type = match self.bounce_policy.action(
+            recipient_email,
+            class,
+            &activity.id,
+            activity_time(activity),
+        )? {
+            BounceAction::Ignore => {
+                log::info!("Soft bounce below the escalation threshold, not recording");
+                return Ok(());
+            }
+            BounceAction::Blocklist => return self.blocklist(activity).await,
+            BounceAction::RecordSoft => BounceType::Soft,
+            BounceAction::RecordHard => BounceType::Hard,
+        };
+        let capaign_uuid_tag = campaign_uuid(activity);
+        let meta = BounceMeta {
+            email_id: Some(activity.email.id.clone()),
+            message_id: activity.email.message.as_ref().map(|x| x.id.clone()),
+            reason: reason.map(|x| x.to_string()),
+            smtp_status: reason.and_then(bounce_policy::smtp_status),
+            template_id: activity.template_id.clone(),
+            timestamp: Some(payload.created_at.clone()),
+        };
+        let mut listmonk_bounce = ListmonkBounce::new(recipient_email, bounce_type).with_meta(meta);
+        if let Some(campaign_uuid) = capaign_uuid_tag {
+            listmonk_bounce = listmonk_bounce.with_campaign_uuid(&campaign_uuid);
+        }
+        if let Some(subscriber_uuid) = subscriber_uuid(activity) {
+            listmonk_bounce = listmonk_bounce.with_subscriber_uuid(&subscriber_uuid);
+        }
+        match self.listmonk_api.record_bounce(listmonk_bounce).await {
+            Ok(_) => {
+                log::info!("Successfully recorded bounce event");
+                Ok(())
+            }
+            Err(e) => {
+                log::error!("Failed to record bounce: {}", e);
+                Err(RestError::Listmonk(e.to_string()))
+            }
+        }
+    }
+}
+
+/// Delivery log entry of an activity. Redeliveries of the webhook share the
+/// event id and are logged once.
+fn delivery_event(payload: &WebhookRequest, activity: &ActivityData) -> DeliveryEvent {
+    DeliveryEvent::new(
+        payload.event.as_str(),
+        &activity.email.recipient.email,
+        activity_time(activity),
+    )
+    .with_campaign_uuid(campaign_uuid(activity))
+    .with_message_id(activity.email.message.as_ref().map(|x| x.id.clone()))
+    .with_event_id(&activity.id)
+    .with_details(serde_json::json!({
+        "email_id": activity.email.id,
+        "morph": activity.morph,
+    }))
+}
+
+/// When the activity happened according to MailerSend, or now if unknown.
+fn activity_time(activity: &ActivityData) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(&activity.created_at)
+        .map_or_else(|_| Utc::now(), |x| x.with_timezone(&Utc))
+}
+
+fn campaign_uuid(activity: &ActivityData) -> Option<String> {
+    correlation_id(activity, CAMPAIGN_TAG_PREFIX, CAMPAIGN_HEADER)
+}
+
+fn subscriber_uuid(activity: &ActivityData) -> Option<String> {
+    correlation_id(activity, SUBSCRIBER_TAG_PREFIX, SUBSCRIBER_HEADER)
+}
+
+/// Reads an id attached at send time, from the email tags or headers.
+fn correlation_id(activity: &ActivityData, tag_prefix: &str, header: &str) -> Option<String> {
+    let tags = activity.email.tags.as_deref().unwrap_or_default();
+    tracking::tag_value(tags, tag_prefix)
+        .map(|x| x.to_string())
+        .or_else(|| activity.email.header(header))
+}
+
+#[cfg(test)]
+impl WebhookProcessor {
+    /// A processor keeping its state in `database`, which unsubscribes from
+    /// the campaign lists, forwards tracking and escalates the second soft
+    /// bounce within 72 hours.
+    pub fn for_test(listmonk_api: ListmonkAPI, database: &crate::storage::Database) -> Self {
+        WebhookProcessor::new(
+            listmonk_api,
+            UnsubscribeAction::Unsubscribe,
+            UnknownCampaignAction::UnsubscribeAll,
+            TrackingPolicy::new(true),
+            ProcessedWebhooks::new(database.clone(), chrono::Duration::hours(72)).unwrap(),
+            BouncePolicy::new(database.clone(), chrono::Duration::hours(72), 2, None).unwrap(),
+            DeliveryLog::new(database.clone()).unwrap(),
+        )
+    }
+
+    pub fn with_unsubscribe_action(mut self, unsubscribe_action: UnsubscribeAction) -> Self {
+        self.unsubscribe_action = unsubscribe_action;
+        self
+    }
+
+    pub fn with_unknown_campaign_action(
+        mut self,
+        unknown_campaign_action: UnknownCampaignAction,
+    ) -> Self {
+        self.unknown_campaign_action = unknown_campaign_action;
+        self
+    }
+
+    pub fn with_tracking_policy(mut self, tracking_policy: TrackingPolicy) -> Self {
+        self.tracking_policy = tracking_policy;
+        self
+    }
+
+    pub fn with_bounce_policy(mut self, bounce_policy: BouncePolicy) -> Self {
+        self.bounce_policy = bounce_policy;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::listmonk::mock;
+    use crate::storage::Database;
+    use actix_web::{http::StatusCode, ResponseError};
+
+    const BODY: &str = include_str!("../../test/req_bounce.json");
+
+    fn processor(listmonk_api: ListmonkAPI) -> WebhookProcessor {
+        WebhookProcessor::for_test(listmonk_api, &Database::open_in_memory().unwrap())
+    }
+
+    fn payload(body: &str) -> WebhookRequest {
+        serde_json::from_str(body).unwrap()
+    }
+
+    fn unsubscribed(campaign_tag: Option<&str>) -> WebhookRequest {
+        let tags = match campaign_tag {
+            Some(tag) => format!("[\"{}\"]", tag),
+            None => "null".to_string(),
+        };
+        payload(
+            &BODY
+                .replace("activity.soft_bounced", "activity.unsubscribed")
+                .replace("\"tags\": null", &format!("\"tags\": {}", tags)),
+        )
+    }
+
+    fn opened() -> WebhookRequest {
+        payload(
+            &BODY
+                .replace("activity.soft_bounced", "activity.opened")
+                .replace(
+                    "\"tags\": null",
+                    "\"tags\": [\"campaign:a4d516f2\", \"subscriber:1db7ee2a\"]",
+                ),
+        )
+    }
+
+    fn clicked(url: &str) -> WebhookRequest {
+        payload(
+            &BODY
+                .replace("activity.soft_bounced", "activity.clicked")
+                .replace(
+                    r#""object": "recipient_bounce",
+      "reason": "Unknown reason""#,
+                    &format!(r#""object": "click", "url": "{}""#, url),
+                ),
+        )
+    }
+
+    fn subscriber_response() -> serde_json::Value {
+        serde_json::json!({
+            "data": {
+                "results": [{
+                    "id": 3,
+                    "uuid": "6a9d1d7e",
+                    "email": "sober.pl@gmail.com",
+                    "lists": [{"id": 1}, {"id": 2}]
+                }]
+            }
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_spam_complaint_with_invalid_email_is_rejected() {
+        let payload = payload(
+            &BODY
+                .replace("activity.soft_bounced", "activity.spam_complaint")
+                .replace("sober.pl@gmail.com", "not-an-email"),
+        );
+        let processor = processor(ListmonkAPI::new("http://127.0.0.1:1", "user", "password"));
+        let error = processor.process(&payload).await.unwrap_err();
+        assert!(matches!(error, RestError::InvalidRecipient(_)));
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_unsubscribed_from_campaign_lists() {
+        let (api, requests) = mock::listmonk(&[
+            ("GET /api/subscribers", subscriber_response()),
+            (
+                "GET /api/campaigns",
+                serde_json::json!({
+                    "data": {"results": [{"id": 7, "uuid": "a4d516f2", "lists": [{"id": 2}]}]}
+                }),
+            ),
+        ]);
+        processor(api)
+            .process(&unsubscribed(Some("campaign:a4d516f2")))
+            .await
+            .unwrap();
+        assert_eq!(
+            requests.find("PUT /api/subscribers/lists")[0].body,
+            serde_json::json!({"ids": [3], "action": "unsubscribe", "target_list_ids": [2]})
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_unsubscribed_without_campaign_leaves_all_lists() {
+        let (api, requests) = mock::listmonk(&[("GET /api/subscribers", subscriber_response())]);
+        processor(api).process(&unsubscribed(None)).await.unwrap();
+        assert_eq!(
+            requests.find("PUT /api/subscribers/lists")[0].body["target_list_ids"],
+            serde_json::json!([1, 2])
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_unsubscribed_from_unknown_campaign_blocklists_subscriber() {
+        let (api, requests) = mock::listmonk(&[
+            ("GET /api/subscribers", subscriber_response()),
+            (
+                "GET /api/campaigns",
+                serde_json::json!({"data": {"results": []}}),
+            ),
+        ]);
+        processor(api)
+            .with_unknown_campaign_action(UnknownCampaignAction::Blocklist)
+            .process(&unsubscribed(Some("campaign:a4d516f2")))
+            .await
+            .unwrap();
+        assert!(requests.find("PUT /api/subscribers/lists").is_empty());
+        assert_eq!(
+            requests.find("PUT /api/subscribers/blocklist")[0].body,
+            serde_json::json!({"ids": [3]})
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_unsubscribed_blocklists_subscriber() {
+        let (api, requests) = mock::listmonk(&[("GET /api/subscribers", subscriber_response())]);
+        processor(api)
+            .with_unsubscribe_action(UnsubscribeAction::Blocklist)
+            .process(&unsubscribed(Some("campaign:a4d516f2")))
+            .await
+            .unwrap();
+        assert_eq!(
+            requests.find("PUT /api/subscribers/blocklist")[0].body,
+            serde_json::json!({"ids": [3]})
+        );
+        assert!(requests.find("PUT /api/subscribers/lists").is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_unsubscribed_unknown_subscriber_is_ignored() {
+        let (api, requests) = mock::listmonk(&[(
+            "GET /api/subscribers",
+            serde_json::json!({"data": {"results": []}}),
+        )]);
+        processor(api).process(&unsubscribed(None)).await.unwrap();
+        assert_eq!(requests.all().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_opened_is_forwarded_once() {
+        let (api, requests) = mock::listmonk(&[]);
+        let processor = processor(api);
+        for _ in 0..2 {
+            processor.process(&opened()).await.unwrap();
+        }
+        assert_eq!(
+            requests
+                .find("GET /campaign/a4d516f2/1db7ee2a/px.png")
+                .len(),
+            1
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_clicked_tracked_link_is_not_counted_twice() {
+        let (api, requests) = mock::listmonk(&[]);
+        processor(api)
+            .process(&clicked(
+                "https://listmonk.app/link/5e3b1f7a/a4d516f2/1db7ee2a",
+            ))
+            .await
+            .unwrap();
+        assert!(requests.all().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_clicked_untracked_link_is_ignored() {
+        let (api, requests) = mock::listmonk(&[]);
+        processor(api)
+            .process(&clicked("https://devdot.blog/article"))
+            .await
+            .unwrap();
+        assert!(requests.all().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_opened_is_ignored_without_forwarding() {
+        let (api, requests) = mock::listmonk(&[]);
+        processor(api)
+            .with_tracking_policy(TrackingPolicy::new(false))
+            .process(&opened())
+            .await
+            .unwrap();
+        assert!(requests.all().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_bounce_uses_correlation_headers() {
+        let (api, requests) = mock::listmonk(&[]);
+        let payload = payload(
+            &BODY
+                .replace("activity.soft_bounced", "activity.hard_bounced")
+                .replace(
+                    "\"headers\": null",
+                    r#""headers": [
+                    {"name": "X-Listmonk-Campaign", "value": "a4d516f2"},
+                    {"name": "X-Listmonk-Subscriber", "value": "1db7ee2a"}
+                ]"#,
+                ),
+        );
+        processor(api).process(&payload).await.unwrap();
+        let bounce = &requests.find("POST /webhooks/bounce")[0].body;
+        assert_eq!(bounce["campaign_uuid"], "a4d516f2");
+        assert_eq!(bounce["subscriber_uuid"], "1db7ee2a");
+        assert_eq!(bounce["email"], "sober.pl@gmail.com");
+    }
+
+    #[actix_rt::test]
+    async fn test_spam_complaint_blocklists_subscriber_by_uuid() {
+        let (api, requests) = mock::listmonk(&[("GET /api/subscribers", subscriber_response())]);
+        let payload = payload(
+            &BODY
+                .replace("activity.soft_bounced", "activity.spam_complaint")
+                .replace("\"tags\": null", "\"tags\": [\"subscriber:6a9d1d7e\"]"),
+        );
+        processor(api).process(&payload).await.unwrap();
+        let lookup = &requests.find("GET /api/subscribers")[0];
+        assert!(lookup.query.contains("subscribers.uuid+%3D+%276a9d1d7e%27"));
+        assert_eq!(
+            requests.find("PUT /api/subscribers/blocklist")[0].body,
+            serde_json::json!({"ids": [3]})
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_soft_bounces_are_escalated_to_hard_bounce() {
+        let (api, requests) = mock::listmonk(&[]);
+        let processor = processor(api);
+        for event_id in ["62f114f8165fe0d8db0288e5", "62f114f8165fe0d8db0288e6"] {
+            let payload = payload(&BODY.replace("62f114f8165fe0d8db0288e5", event_id));
+            processor.process(&payload).await.unwrap();
+        }
+        let bounces = requests.find("POST /webhooks/bounce");
+        assert_eq!(bounces.len(), 1);
+        assert_eq!(bounces[0].body["type"], "hard");
+    }
+
+    #[actix_rt::test]
+    async fn test_retried_soft_bounce_is_counted_once() {
+        let database = Database::open_in_memory().unwrap();
+        let bounce_policy =
+            BouncePolicy::new(database.clone(), chrono::Duration::hours(72), 1, Some(2)).unwrap();
+        let processor = |listmonk_api| {
+            WebhookProcessor::for_test(listmonk_api, &database)
+                .with_bounce_policy(bounce_policy.clone())
+        };
+        let payload = payload(BODY);
+        let unavailable = ListmonkAPI::new("http://127.0.0.1:1", "listmonk", "listmonk");
+        assert!(processor(unavailable).process(&payload).await.is_err());
+        let (api, requests) = mock::listmonk(&[("GET /api/subscribers", subscriber_response())]);
+        processor(api).process(&payload).await.unwrap();
+        let count: u32 = database
+            .connection()
+            .query_row("SELECT COUNT(*) FROM soft_bounces", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+        let bounces = requests.find("POST /webhooks/bounce");
+        assert_eq!(bounces.len(), 1);
+        assert_eq!(bounces[0].body["type"], "hard");
+        assert!(requests.find("PUT /api/subscribers/blocklist").is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_redelivered_webhook_is_processed_once() {
+        let (api, requests) = mock::listmonk(&[]);
+        let processor = processor(api);
+        let payload = payload(&BODY.replace("activity.soft_bounced", "activity.hard_bounced"));
+        for _ in 0..2 {
+            processor.process(&payload).await.unwrap();
+        }
+        assert_eq!(requests.find("POST /webhooks/bounce").len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_mailbox_full_hard_bounce_is_counted_as_soft() {
+        let (api, requests) = mock::listmonk(&[]);
+        let payload = payload(
+            &BODY
+                .replace("activity.soft_bounced", "activity.hard_bounced")
+                .replace("Unknown reason", "552 5.2.2 Mailbox full"),
+        );
+        processor(api).process(&payload).await.unwrap();
+        assert!(requests.find("POST /webhooks/bounce").is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_unknown_events_are_ignored() {
+        let (api, requests) = mock::listmonk(&[]);
+        let payload = payload(
+            r#"{
+                "type": "activity.forwarded",
+                "domain_id": "neqvygmm93wg0p7w",
+                "created_at": "2024-01-08T14:46:07.442816Z",
+                "webhook_id": "jpzkmgqdyml059v7",
+                "url": "https://example.com/webhooks",
+                "data": {"id": "62f114f8165fe0d8db0288e9"}
+            }"#,
+        );
+        processor(api).process(&payload).await.unwrap();
+        assert!(requests.all().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_bounce_meta() {
+        let (api, requests) = mock::listmonk(&[]);
+        let payload = payload(
+            &BODY
+                .replace("activity.soft_bounced", "activity.hard_bounced")
+                .replace("Unknown reason", "550 5.1.1 User unknown"),
+        );
+        processor(api).process(&payload).await.unwrap();
+        assert_eq!(
+            requests.find("POST /webhooks/bounce")[0].body["meta"],
+            serde_json::json!({
+                "email_id": "62f114f7165fe0d8db0288e2",
+                "message_id": "62fb66bef54a112e920b5493",
+                "reason": "550 5.1.1 User unknown",
+                "smtp_status": "5.1.1",
+                "template_id": "0z76k5jg0o3yeg2d",
+                "timestamp": "2024-01-08T14:46:07.442816Z"
+            })
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_activities_are_logged_once() {
+        let (api, _) = mock::listmonk(&[]);
+        let database = Database::open_in_memory().unwrap();
+        let delivery_log = DeliveryLog::new(database.clone()).unwrap();
+        let processor = WebhookProcessor::for_test(api, &database);
+        for _ in 0..2 {
+            processor.process(&opened()).await.unwrap();
+        }
+        let timeline = delivery_log.campaign_timeline("a4d516f2", 100, 0).unwrap();
+        assert_eq!(timeline.len(), 1);
+        let event = &timeline[0].event;
+        assert_eq!(event.event, "activity.opened");
+        assert_eq!(event.recipient, "sober.pl@gmail.com");
+        assert_eq!(
+            event.message_id.as_deref(),
+            Some("62fb66bef54a112e920b5493")
+        );
+        assert_eq!(event.created_at, "2022-08-08T13:51:52.747000Z");
+        assert_eq!(event.details["morph"]["object"], "recipient_bounce");
+    }
+}
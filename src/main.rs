@@ -1,15 +1,37 @@
+mod admin;
+mod auth;
 mod config;
+mod error;
 mod listmonk;
 mod mailersend;
+mod storage;
 
 use actix_jobs::{run_forever, Scheduler};
 use actix_web::{web, App, HttpServer};
+use auth::Authenticator;
 use clap::Parser;
 use config::Configuration;
 use dotenv;
-use listmonk::api::ListmonkAPI;
-use mailersend::{api::MailerSendAPI, buffer::Buffer, job::OutgoingEmailsJob};
-use std::io;
+use listmonk::{
+    api::ListmonkAPI, headers::HeaderPolicy, sender::SenderPolicy, tracking::TrackingPolicy,
+};
+use mailersend::{
+    api::{EmailAddress, MailerSendAPI},
+    bounce_policy::BouncePolicy,
+    buffer::Buffer,
+    bulk_status::BulkEmailStore,
+    dead_letter::DeadLetterStore,
+    delivery_log::DeliveryLog,
+    inbox::WebhookInbox,
+    job::{BulkStatusJob, DeliveryLogPruneJob, OutgoingEmailsJob, WebhookInboxJob},
+    processed::ProcessedWebhooks,
+    rate_limiter::RateLimiter,
+    retry::RetryPolicy,
+    signature::SignatureVerifier,
+    webhook::WebhookProcessor,
+};
+use std::{io, time::Duration};
+use storage::Database;
 
 #[actix_web::main]
 async fn main() -> io::Result<()> {
@@ -17,44 +39,198 @@ async fn main() -> io::Result<()> {
     let config = Configuration::parse();
     simple_logger::init_with_level(config.log_level).unwrap();
 
-    let shared_email_buffer = Buffer::new();
+    if config.signing_secret.is_none() {
+        if config.require_signing_secret {
+            log::error!("MailerSend webhooks signing secret is required but not configured");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Missing MailerSend webhooks signing secret",
+            ));
+        }
+        log::warn!("MailerSend webhooks signing secret not configured, webhook signatures will not be verified");
+    }
+
+    if config.auth_username.is_some() != config.auth_password.is_some() {
+        log::error!("Both the auth username and password must be configured");
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Incomplete basic auth credentials",
+        ));
+    }
+    let authenticator = Authenticator::new(
+        config.auth_username.clone(),
+        config.auth_password.clone(),
+        config.auth_token.clone(),
+    );
+    if !authenticator.is_enabled() {
+        log::warn!(
+            "Auth credentials not configured, messenger and admin endpoints are not protected"
+        );
+    }
+
+    let database = Database::open(&config.database_path).map_err(|e| {
+        io::Error::other(format!(
+            "Failed to open database {}: {}",
+            config.database_path, e
+        ))
+    })?;
+    let shared_email_buffer = Buffer::new(database.clone()).map_err(io::Error::other)?;
+    let dead_letters = DeadLetterStore::new(database.clone()).map_err(io::Error::other)?;
+    let bulk_emails = BulkEmailStore::new(database.clone()).map_err(io::Error::other)?;
+    let webhook_inbox = WebhookInbox::new(database.clone()).map_err(io::Error::other)?;
+    let delivery_log = DeliveryLog::new(database.clone()).map_err(io::Error::other)?;
+    let processed_webhooks = ProcessedWebhooks::new(
+        database.clone(),
+        chrono::Duration::hours(config.webhook_dedupe_ttl_hours),
+    )
+    .map_err(io::Error::other)?;
+    let bounce_policy = BouncePolicy::new(
+        database.clone(),
+        chrono::Duration::hours(config.soft_bounce_window_hours),
+        config.soft_bounce_threshold,
+        config.soft_bounce_blocklist_threshold,
+    )
+    .map_err(io::Error::other)?;
     let mailersend_api = MailerSendAPI::new(
         &config.mailersend_api_endpoint,
         &config.mailersend_api_token,
-        config.api_bulk_req_per_min,
+        RateLimiter::per_minute(config.api_bulk_req_per_min, config.api_bulk_burst),
+        RetryPolicy::new(
+            config.api_retry_attempts,
+            Duration::from_millis(config.api_retry_base_delay_ms),
+            Duration::from_millis(config.api_retry_max_delay_ms),
+        ),
+    );
+    let listmonk_api = ListmonkAPI::new(
+        &config.listmonk_api_endpoint,
+        &config.listmonk_api_username,
+        &config.listmonk_api_password,
+    );
+    let tracking_policy = TrackingPolicy::new(config.forward_tracking);
+    let webhook_processor = WebhookProcessor::new(
+        listmonk_api.clone(),
+        config.unsubscribe_action,
+        config.unknown_campaign_action,
+        tracking_policy,
+        processed_webhooks,
+        bounce_policy,
+        delivery_log.clone(),
     );
 
     let mut scheduler = Scheduler::new();
     scheduler.add(Box::new(OutgoingEmailsJob::new(
         &config.outgoing_cron,
-        mailersend_api,
+        mailersend_api.clone(),
         shared_email_buffer.clone(),
+        bulk_emails.clone(),
+        dead_letters.clone(),
+        delivery_log.clone(),
         config.api_email_bulk_size,
     )));
+    scheduler.add(Box::new(BulkStatusJob::new(
+        &config.bulk_status_cron,
+        mailersend_api,
+        bulk_emails,
+        dead_letters.clone(),
+        delivery_log.clone(),
+    )));
+    scheduler.add(Box::new(WebhookInboxJob::new(
+        &config.webhook_inbox_cron,
+        webhook_inbox.clone(),
+        webhook_processor,
+        RetryPolicy::new(
+            config.webhook_retry_attempts,
+            Duration::from_secs(config.webhook_retry_base_delay_secs),
+            Duration::from_secs(config.webhook_retry_max_delay_secs),
+        ),
+    )));
+    scheduler.add(Box::new(DeliveryLogPruneJob::new(
+        &config.delivery_log_prune_cron,
+        delivery_log.clone(),
+        chrono::Duration::days(config.delivery_log_retention_days),
+    )));
     log::info!("Starting scheduler");
     run_forever(scheduler);
 
-    let listmonk_api = ListmonkAPI::new(
-        &config.listmonk_api_endpoint,
-        &config.listmonk_api_username,
-        &config.listmonk_api_password,
+    let header_policy = HeaderPolicy::new(
+        &config.allowed_headers,
+        &config.denied_headers,
+        config
+            .listmonk_public_url
+            .as_ref()
+            .unwrap_or(&config.listmonk_api_endpoint),
     );
+    let default_from = match &config.default_from {
+        None => None,
+        Some(default_from) => Some(EmailAddress::from_string(default_from).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid default from address {}: {}", default_from, e),
+            )
+        })?),
+    };
+    let sender_policy = SenderPolicy::new(&config.allowed_senders, default_from);
+    let signature_verifier = SignatureVerifier::new(config.signing_secret.clone());
     let host = config.host.clone();
     let port = config.port;
     log::info!("Starting server on {}:{}", host, port);
     HttpServer::new(move || {
         App::new()
+            .app_data(listmonk::rest::json_config())
             .app_data(web::Data::new(shared_email_buffer.clone()))
+            .app_data(web::Data::new(dead_letters.clone()))
             .app_data(web::Data::new(listmonk_api.clone()))
+            .app_data(web::Data::new(header_policy.clone()))
+            .app_data(web::Data::new(sender_policy.clone()))
+            .app_data(web::Data::new(signature_verifier.clone()))
+            .app_data(web::Data::new(tracking_policy))
+            .app_data(web::Data::new(webhook_inbox.clone()))
+            .app_data(web::Data::new(delivery_log.clone()))
+            .app_data(web::Data::new(authenticator.clone()))
             .app_data(web::Data::new(config.clone()))
-            .route(
-                "/api/messenger",
-                web::post().to(listmonk::rest::messenger_handler),
+            .service(
+                web::resource("/api/messenger")
+                    .wrap_fn(auth::authenticate)
+                    .route(web::post().to(listmonk::rest::messenger_handler)),
             )
             .route(
                 "/webhooks/service/mailersend",
                 web::post().to(mailersend::rest::webhook_handler),
             )
+            .service(
+                web::scope("/admin")
+                    .wrap_fn(auth::authenticate)
+                    .route(
+                        "/dead-letters",
+                        web::get().to(admin::rest::list_dead_letters),
+                    )
+                    .route(
+                        "/dead-letters/{id}",
+                        web::get().to(admin::rest::get_dead_letter),
+                    )
+                    .route(
+                        "/dead-letters/{id}",
+                        web::delete().to(admin::rest::delete_dead_letter),
+                    )
+                    .route(
+                        "/dead-letters/{id}/replay",
+                        web::post().to(admin::rest::replay_dead_letter),
+                    )
+                    .route("/webhooks", web::get().to(admin::rest::list_webhooks))
+                    .route("/webhooks/{id}", web::get().to(admin::rest::get_webhook))
+                    .route(
+                        "/webhooks/{id}/retry",
+                        web::post().to(admin::rest::retry_webhook),
+                    )
+                    .route(
+                        "/campaigns/{uuid}/events",
+                        web::get().to(admin::rest::campaign_events),
+                    )
+                    .route(
+                        "/recipients/{email}/events",
+                        web::get().to(admin::rest::recipient_events),
+                    ),
+            )
     })
     .bind((host, port))?
     .run()